@@ -0,0 +1,272 @@
+use std::sync::Arc;
+
+use actix_web::{
+    http::header::ContentType,
+    web::{Data, Json, Path, Query},
+    HttpMessage, HttpRequest, HttpResponse,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use validator::Validate;
+
+use crate::{
+    auth::{cache::RevocationCache, role::Role, token::Claims},
+    db::Conn,
+    email::{
+        templates::{Locale, Template},
+        Email,
+    },
+    error::AppError,
+    models::{
+        comment::DeleteComment,
+        cursor::{page_size, Cursor},
+        institution::{CreateInstitution, Institution, Removal, UpdateInstitution},
+        outbox::{OutboxEntry, OutboxStatus},
+        post::DeletePost,
+        user::{User, UserSummary},
+    },
+};
+
+// The routes below are mounted in scopes guarded by `middleware::admin` or
+// `middleware::moderator`.
+
+// ==================================================== INSTITUTIONS ======================================================
+
+#[actix_web::get("")]
+pub async fn list_institutions(conn: Conn) -> Result<HttpResponse, AppError> {
+    let institutions = Institution::all_from_db(&conn).await?;
+
+    Ok(HttpResponse::Ok().json(json!(institutions)))
+}
+
+#[actix_web::post("")]
+pub async fn create_institution(
+    conn: Conn,
+    form: Json<CreateInstitution>,
+) -> Result<HttpResponse, AppError> {
+    form.validate()?;
+
+    let institution = form.insert_into_db(&conn).await?;
+
+    Ok(HttpResponse::Created().json(json!(institution)))
+}
+
+#[actix_web::patch("/{id}")]
+pub async fn update_institution(
+    conn: Conn,
+    id: Path<String>,
+    form: Json<UpdateInstitution>,
+) -> Result<HttpResponse, AppError> {
+    form.validate()?;
+
+    let institution = form
+        .update_into_db(&id, &conn)
+        .await?
+        .ok_or_else(|| AppError::not_found("Institution not found"))?;
+
+    Ok(HttpResponse::Ok().json(json!(institution)))
+}
+
+#[actix_web::delete("/{id}")]
+pub async fn delete_institution(conn: Conn, id: Path<String>) -> Result<HttpResponse, AppError> {
+    match Institution::delete_from_db(&id, &conn).await? {
+        Removal::Deleted => Ok(HttpResponse::NoContent().finish()),
+        Removal::NotFound => Err(AppError::not_found("Institution not found")),
+        Removal::InUse => Err(AppError::Conflict(
+            "institution_in_use",
+            "Users still belong to this institution".to_string(),
+        )),
+    }
+}
+
+// ==================================================== USERS ======================================================
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CountQuery {
+    count: Option<i32>,
+    cursor: Option<String>,
+}
+
+#[actix_web::get("")]
+pub async fn list_users(conn: Conn, query: Query<CountQuery>) -> Result<HttpResponse, AppError> {
+    let limit = page_size(query.count);
+    let cursor = Cursor::from_query(query.cursor.as_deref())?;
+
+    let users = UserSummary::retrieve_from_db(&conn, limit, cursor.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(json!(users)))
+}
+
+#[actix_web::post("/{user_id}/deactivate")]
+pub async fn deactivate_user(
+    req: HttpRequest,
+    conn: Conn,
+    cache: Data<RevocationCache>,
+    user_id: Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let admin = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+    if admin.sub == *user_id {
+        return Err(AppError::BadRequest(
+            "cannot_deactivate_self",
+            "You can't deactivate your own account".to_string(),
+        ));
+    }
+
+    if !User::deactivate(&user_id, &conn).await? {
+        return Err(AppError::not_found("No active account with this id"));
+    }
+    cache.revoke_user(&user_id);
+    info!("{} deactivated user {}", admin.sub, user_id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoleForm {
+    role: Role,
+}
+
+#[actix_web::put("/{user_id}/role")]
+pub async fn change_role(
+    req: HttpRequest,
+    conn: Conn,
+    cache: Data<RevocationCache>,
+    user_id: Path<String>,
+    form: Json<RoleForm>,
+) -> Result<HttpResponse, AppError> {
+    let admin = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+    // Keeps the last admin from locking everyone out.
+    if admin.sub == *user_id {
+        return Err(AppError::BadRequest(
+            "cannot_change_own_role",
+            "You can't change your own role".to_string(),
+        ));
+    }
+
+    if !User::set_role(&user_id, form.role, &conn).await? {
+        return Err(AppError::not_found("User not found"));
+    }
+    cache.revoke_user(&user_id);
+    info!("{} made user {} {}", admin.sub, user_id, form.role.as_str());
+
+    Ok(HttpResponse::Ok().json(json!({ "id": *user_id, "role": form.role })))
+}
+
+// ==================================================== CONTENT ======================================================
+
+#[actix_web::delete("/{post_id}")]
+pub async fn remove_post(
+    req: HttpRequest,
+    conn: Conn,
+    post_id: Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let moderator = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+
+    if !DeletePost::remove_from_db(&post_id, &conn).await? {
+        return Err(AppError::not_found("Post not found"));
+    }
+    info!("{} removed post {}", moderator.sub, post_id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[actix_web::delete("/{comment_id}")]
+pub async fn remove_comment(
+    req: HttpRequest,
+    conn: Conn,
+    comment_id: Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let moderator = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+
+    if !DeleteComment::remove_from_db(&comment_id, &conn).await? {
+        return Err(AppError::not_found("Comment not found"));
+    }
+    info!("{} removed comment {}", moderator.sub, comment_id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// ==================================================== OUTBOX ======================================================
+
+#[derive(Debug, Deserialize, Serialize)]
+struct OutboxQuery {
+    status: Option<OutboxStatus>,
+    count: Option<i32>,
+    cursor: Option<String>,
+}
+
+#[actix_web::get("")]
+pub async fn list_outbox(conn: Conn, query: Query<OutboxQuery>) -> Result<HttpResponse, AppError> {
+    let limit = page_size(query.count);
+    let cursor = Cursor::from_query(query.cursor.as_deref())?;
+
+    let entries =
+        OutboxEntry::retrieve_from_db(query.status, &conn, limit, cursor.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(json!(entries)))
+}
+
+#[actix_web::post("/{email_id}/retry")]
+pub async fn retry_email(
+    conn: Conn,
+    mailer: Data<Email>,
+    email_id: Path<String>,
+) -> Result<HttpResponse, AppError> {
+    if !OutboxEntry::retry(&email_id, &conn).await? {
+        return Err(AppError::not_found("No failed email with this id"));
+    }
+    mailer.queued().notify_one();
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+// ==================================================== EMAIL TEMPLATES ======================================================
+
+#[actix_web::get("")]
+pub async fn list_email_templates() -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "templates": Template::ALL,
+        "locales": Locale::ALL,
+    }))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum PreviewFormat {
+    /// The subject and both bodies.
+    #[default]
+    Json,
+    Html,
+    Text,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PreviewQuery {
+    locale: Option<Locale>,
+    #[serde(default)]
+    format: PreviewFormat,
+}
+
+/// Renders a template with made-up data, the way a user with `locale` would
+/// get it.
+#[actix_web::get("/{template}/preview")]
+pub async fn preview_email(
+    mailer: Data<Email>,
+    template: Path<String>,
+    query: Query<PreviewQuery>,
+) -> Result<HttpResponse, AppError> {
+    let template = Template::from_name(&template)
+        .ok_or_else(|| AppError::not_found("No email template with this name"))?;
+    let rendered = mailer.preview(template, query.locale.unwrap_or_default())?;
+
+    Ok(match query.format {
+        PreviewFormat::Json => HttpResponse::Ok().json(rendered),
+        PreviewFormat::Html => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(rendered.html),
+        PreviewFormat::Text => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(rendered.text),
+    })
+}
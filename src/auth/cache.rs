@@ -0,0 +1,125 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How many validated tokens are remembered at most.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+/// How long a token is trusted without asking the database again. This also
+/// bounds how long a revocation made by another server process can go
+/// unnoticed here.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+struct Entry {
+    user: String,
+    family: Option<String>,
+    expires: Instant,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    /// Keys in insertion order, for evicting the oldest entry when full.
+    order: VecDeque<String>,
+    /// Bumped on every revocation so a check that raced with one is not cached.
+    epoch: u64,
+}
+
+/// Remembers access tokens the database recently confirmed as valid, so the
+/// JWT middleware doesn't have to look them up on every request.
+///
+/// Every revocation made by this process must go through `revoke_*` so that a
+/// revoked token is never served from the cache.
+pub struct RevocationCache {
+    inner: Mutex<Inner>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl RevocationCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// Taken before checking a token against the database and handed back to
+    /// `insert`, which ignores the result if something was revoked meanwhile.
+    pub fn epoch(&self) -> u64 {
+        self.inner.lock().unwrap().epoch
+    }
+
+    pub fn is_valid(&self, key: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.entries.get(key) {
+            Some(entry) if entry.expires > Instant::now() => true,
+            Some(_) => {
+                inner.entries.remove(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Records that the token `key` was valid when `epoch` was taken. The
+    /// entry never outlives `lifetime`, the time the token has left.
+    pub fn insert(
+        &self,
+        key: &str,
+        user: &str,
+        family: Option<&str>,
+        lifetime: Duration,
+        epoch: u64,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.epoch != epoch {
+            return;
+        }
+
+        while inner.entries.len() >= self.capacity {
+            match inner.order.pop_front() {
+                Some(oldest) => {
+                    inner.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+
+        let entry = Entry {
+            user: user.to_string(),
+            family: family.map(str::to_string),
+            expires: Instant::now() + self.ttl.min(lifetime),
+        };
+        if inner.entries.insert(key.to_string(), entry).is_none() {
+            inner.order.push_back(key.to_string());
+        }
+    }
+
+    pub fn revoke_token(&self, key: &str) {
+        self.revoke(|k, _| k == key);
+    }
+
+    pub fn revoke_family(&self, family: &str) {
+        self.revoke(|_, e| e.family.as_deref() == Some(family));
+    }
+
+    pub fn revoke_user(&self, user: &str) {
+        self.revoke(|_, e| e.user == user);
+    }
+
+    fn revoke(&self, matches: impl Fn(&str, &Entry) -> bool) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.epoch += 1;
+        inner.entries.retain(|k, e| !matches(k, e));
+
+        let Inner { entries, order, .. } = &mut *inner;
+        order.retain(|k| entries.contains_key(k));
+    }
+}
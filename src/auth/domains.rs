@@ -0,0 +1,89 @@
+use std::fmt;
+
+use regex::Regex;
+
+/// An institutional email domain that may register, optionally with the
+/// format its roll numbers must follow.
+#[derive(Debug, Clone)]
+pub struct AllowedDomain {
+    pub domain: String,
+    roll_format: Option<String>,
+    roll_regex: Option<Regex>,
+}
+
+impl AllowedDomain {
+    /// `roll_format` must match the whole roll number; it is anchored here.
+    pub fn new(domain: &str, roll_format: Option<&str>) -> Result<Self, DomainPolicyError> {
+        let domain = domain.trim().trim_start_matches('.').to_lowercase();
+        if domain.is_empty() || domain.contains('@') {
+            return Err(DomainPolicyError(format!("invalid domain `{}`", domain)));
+        }
+
+        let roll_regex = roll_format
+            .map(|f| Regex::new(&format!("^(?:{})$", f)))
+            .transpose()
+            .map_err(|e| DomainPolicyError(format!("invalid roll format for {}: {}", domain, e)))?;
+
+        Ok(Self {
+            domain,
+            roll_format: roll_format.map(str::to_string),
+            roll_regex,
+        })
+    }
+
+    pub fn roll_format(&self) -> Option<&str> {
+        self.roll_format.as_deref()
+    }
+
+    pub fn roll_is_valid(&self, roll: &str) -> bool {
+        self.roll_regex.as_ref().is_none_or(|f| f.is_match(roll))
+    }
+}
+
+/// The email domains configured at startup. Each becomes an institution, or
+/// has its roll number format updated if it already is one; registration is
+/// then decided by the institutions table.
+#[derive(Debug, Clone)]
+pub struct DomainPolicy {
+    domains: Vec<AllowedDomain>,
+}
+
+impl DomainPolicy {
+    pub fn new(domains: Vec<AllowedDomain>) -> Self {
+        Self { domains }
+    }
+
+    /// Parses a `;` separated list of domains, each optionally followed by
+    /// `=` and a roll number regex, e.g. `dcrustm.org=[0-9]{8};iitd.ac.in`.
+    pub fn parse(spec: &str) -> Result<Self, DomainPolicyError> {
+        let domains = spec
+            .split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| match entry.split_once('=') {
+                Some((domain, format)) => AllowedDomain::new(domain, Some(format.trim())),
+                None => AllowedDomain::new(entry, None),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if domains.is_empty() {
+            return Err(DomainPolicyError("no allowed email domains".to_string()));
+        }
+
+        Ok(Self::new(domains))
+    }
+
+    pub fn domains(&self) -> &[AllowedDomain] {
+        &self.domains
+    }
+}
+
+#[derive(Debug)]
+pub struct DomainPolicyError(String);
+
+impl fmt::Display for DomainPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for DomainPolicyError {}
@@ -1,47 +1,59 @@
 use std::sync::Arc;
 
 use actix_web::{
-    error,
-    web::{Data, Json},
+    http::header,
+    web::{Data, Json, Path},
     HttpMessage, HttpRequest, HttpResponse,
 };
-use chrono::Duration;
+use cache::RevocationCache;
+use chrono::Utc;
 use libsql::{params, Connection};
-use log::{error, info};
+use log::{info, warn};
+use role::Role;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use token::{Claims, JWT};
-use uuid::Timestamp;
+use uuid::{Timestamp, Uuid};
 
 use validator::Validate;
 use validator_derive::Validate;
 
-use crate::models::{otp::Otp, user::User};
-use crate::{email::Email, models::user::CreateUser};
+use crate::db::Conn;
+use crate::email::templates::{AlertKind, Locale, SecurityAlert};
+use crate::error::AppError;
+use crate::models::institution::Institution;
+use crate::models::otp::{Otp, OtpCheck, OtpPurpose, OtpRequest};
+use crate::models::session::{CreateSession, Rotation, Session};
+use crate::{
+    email::Email,
+    models::user::{CreateUser, Recipient, User},
+};
 
+pub mod cache;
+pub mod domains;
+pub mod role;
 pub mod token;
 
 // ======================================== REGISTER USER FOR FURTHER VERIFICATION ==========================================
 
 #[actix_web::post("/register")]
 pub async fn register_user(
+    req: HttpRequest,
     user: Json<CreateUser>,
-    conn: Data<Connection>,
-) -> Result<HttpResponse, actix_web::Error> {
-    user.validate().map_err(|validation_errors| {
-        info!("User validation failed: {:?}", validation_errors);
-        error::ErrorBadRequest(serde_json::to_string(&validation_errors).unwrap_or_default())
-    })?;
+    conn: Conn,
+) -> Result<HttpResponse, AppError> {
+    user.validate()?;
 
     if user.username.contains(" ") || user.username.contains("@") || user.username.contains("/") {
         info!("Username cannot contain spaces or @");
-        return Ok(HttpResponse::BadRequest().body("Username cannot contain spaces or @"));
+        return Err(AppError::BadRequest(
+            "invalid_username",
+            "Username cannot contain spaces or @".to_string(),
+        ));
     }
 
-    if !user.email.ends_with("dcrustm.org") {
-        info!("Only DCRUSTM email addresses are allowed");
-        return Ok(HttpResponse::BadRequest().body("Only DCRUSTM email addresses are allowed"));
-    }
+    let institution = Institution::require_for_email(&user.email, &conn).await?;
+    institution.check_roll(&user.roll)?;
 
     info!("User data is correct: {:?}", user);
     let user = user.into_inner();
@@ -51,26 +63,56 @@ pub async fn register_user(
     let ts = Timestamp::from_unix(&context, 1497624119, 1234);
     let uuid = uuid::Uuid::new_v6(ts, &[1, 2, 3, 4, 5, 6]);
 
-    let mut row = conn
+    // Email, username and roll are all unique. Check them here so a clash
+    // is a conflict rather than a failed insert.
+    let mut rows = conn
         .query(
-            "SELECT * FROM users WHERE email = ?1",
-            params![user.email.clone()],
+            r#"
+            SELECT email = ?1, username = ?2, roll = ?3 FROM users
+            WHERE email = ?1 OR username = ?2 OR roll = ?3
+            "#,
+            params![user.email.clone(), user.username.clone(), user.roll.clone()],
         )
-        .await
-        .map_err(|e| {
-            error!("Error checking if user exists: {:?}", e);
-            error::ErrorInternalServerError("Failed to register user. Please try again.")
-        })?;
+        .await?;
 
-    if let Ok(Some(_)) = row.next().await {
+    let (mut email, mut username, mut roll) = (false, false, false);
+    while let Some(row) = rows.next().await? {
+        email |= row.get::<bool>(0)?;
+        username |= row.get::<bool>(1)?;
+        roll |= row.get::<bool>(2)?;
+    }
+
+    if email {
         info!("User with this email already exists");
-        return Ok(HttpResponse::BadRequest().body("User with this email already exists"));
+        return Err(AppError::Conflict(
+            "email_taken",
+            "User with this email already exists".to_string(),
+        ));
+    }
+    if username {
+        return Err(AppError::Conflict(
+            "username_taken",
+            "This username is already taken".to_string(),
+        ));
+    }
+    if roll {
+        return Err(AppError::Conflict(
+            "roll_taken",
+            "An account with this roll number already exists".to_string(),
+        ));
     }
 
-    user.insert_into_db(uuid, conn).await.map_err(|e| {
-        error!("Error inserting user into database: {:?}", e);
-        error::ErrorInternalServerError("Failed to register user. Please try again.")
-    })?;
+    // Emails go out in the language the client asked for until the user
+    // picks another one.
+    let locale = req
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|h| h.to_str().ok())
+        .map(Locale::negotiate)
+        .unwrap_or_default();
+
+    user.insert_into_db(uuid, &institution.id, locale, &conn)
+        .await?;
 
     Ok(HttpResponse::Created().json(json!({
         "user": {
@@ -80,7 +122,11 @@ pub async fn register_user(
             "first_name": user.first_name,
             "last_name": user.last_name,
             "roll": user.roll,
-            "dob": user.dob
+            "dob": user.dob,
+            "institution": {
+                "id": institution.id,
+                "name": institution.name,
+            }
         }
     })))
 }
@@ -95,45 +141,82 @@ pub struct EmailVerificationForm {
 
 #[actix_web::post("/send-otp")]
 pub async fn send_otp(
-    conn: Data<Connection>,
+    conn: Conn,
     email: Json<EmailVerificationForm>,
     mailer: Data<Email>,
-) -> Result<HttpResponse, actix_web::Error> {
-    email.validate().map_err(|validation_errors| {
-        info!("Email validation failed: {:?}", validation_errors);
-        error::ErrorBadRequest(serde_json::to_string(&validation_errors).unwrap_or_default())
-    })?;
+) -> Result<HttpResponse, AppError> {
+    email.validate()?;
 
     let email = email.into_inner();
 
-    if !email.email.ends_with("dcrustm.org") {
-        return Ok(HttpResponse::BadRequest().body("Only DCRUSTM email addresses are allowed"));
-    }
+    Institution::require_for_email(&email.email, &conn).await?;
 
     info!("Email data is correct");
 
-    let otp = Email::generate_otp();
-    info!("OTP generated {otp}");
+    send_verification(email.email, &conn, &mailer).await?;
 
-    // Clone necessary data for background task
-    let mailer_clone = mailer.clone();
+    Ok(HttpResponse::Ok().body("Otp sending initiated"))
+}
 
-    conn.execute(
-        r#"INSERT INTO otps (email, otp, created_at) VALUES (?1, ?2, CURRENT_TIMESTAMP)
-            ON CONFLICT(email) DO UPDATE SET otp = ?2, created_at = CURRENT_TIMESTAMP"#,
-        params![email.email.clone(), otp.clone()],
-    )
-    .await
-    .map_err(|e| {
-        error!("Error inserting OTP into database: {:?}", e);
-        error::ErrorInternalServerError("Failed to send OTP. Please try again.")
-    })?;
+// ======================================== RESEND VERIFICATION ==========================================
+
+#[actix_web::post("/resend-verification")]
+pub async fn resend_verification(
+    conn: Conn,
+    email: Json<EmailVerificationForm>,
+    mailer: Data<Email>,
+) -> Result<HttpResponse, AppError> {
+    email.validate()?;
 
-    let _ = actix_web::rt::spawn(async move {
-        let _ = mailer_clone.send(email.email, otp.clone()).await;
-    });
+    let email = email.into_inner();
 
-    Ok(HttpResponse::Ok().body("Otp sending initiated"))
+    let mut row = conn
+        .query(
+            "SELECT 1 FROM users WHERE email = ?1 AND is_active = FALSE",
+            params![email.email.clone()],
+        )
+        .await?;
+
+    // Like `forgot_password`, answer the same way whether the account is
+    // missing, verified or waiting on a code, so this can't be used to find
+    // out who is registered.
+    if row.next().await?.is_some() {
+        match send_verification(email.email, &conn, &mailer).await {
+            Ok(()) | Err(AppError::TooManyRequests(..)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .body("If an unverified account exists for this email, a verification code has been sent"))
+}
+
+/// Issues a registration OTP for `email` and queues the mail with it.
+async fn send_verification(
+    email: String,
+    conn: &Connection,
+    mailer: &Data<Email>,
+) -> Result<(), AppError> {
+    let to = Recipient::by_email(&email, conn).await?;
+    let otp = Email::generate_otp();
+
+    match Otp::insert_into_db(&email, &otp, OtpPurpose::Registration, conn).await? {
+        OtpRequest::Issued => {}
+        OtpRequest::TooSoon(wait) => {
+            return Err(AppError::TooManyRequests(
+                "otp_cooldown",
+                format!("Please wait {} seconds before requesting another OTP", wait),
+            ))
+        }
+        OtpRequest::Locked(wait) => return Err(otp_locked(wait)),
+    }
+
+    if let Err(e) = mailer.send_otp(&to, &otp, conn).await {
+        Otp::withdraw(&email, &otp, OtpPurpose::Registration, conn).await?;
+        return Err(e);
+    }
+
+    Ok(())
 }
 
 // ======================================== VERIFY OTP FOR EMAIL VERIFICATION ==========================================
@@ -148,86 +231,73 @@ pub struct OTPVerificationForm {
 
 #[actix_web::post("/verify-otp")]
 pub async fn verify_otp(
-    conn: Data<Connection>,
+    req: HttpRequest,
+    conn: Conn,
     jwt: Data<token::JWT>,
+    mailer: Data<Email>,
     form: Json<OTPVerificationForm>,
-) -> Result<HttpResponse, actix_web::Error> {
-    if let Err(e) = form.validate() {
-        return Ok(HttpResponse::BadRequest().json(e));
-    }
+) -> Result<HttpResponse, AppError> {
+    form.validate()?;
 
     let form = form.into_inner();
 
-    let val = conn
-        .query(
-            "SELECT * FROM otps WHERE email = ?1",
+    check_otp(&form.email, OtpPurpose::Registration, &form.otp, &conn).await?;
+
+    let activated = conn
+        .execute(
+            "UPDATE users SET is_active = true WHERE email = ?1 AND is_active IS NOT TRUE",
             params![form.email.clone()],
         )
-        .await
-        .map_err(|_| error::ErrorBadGateway("Something went wrong"))?
+        .await?;
+
+    if activated > 0 {
+        let to = Recipient::by_email(&form.email, &conn).await?;
+        mailer.send_welcome(&to, &conn).await?;
+    }
+
+    let user_id = conn
+        .query("SELECT id FROM users WHERE email = ?1", params!(form.email))
+        .await?
         .next()
-        .await
-        .map_err(|_| error::ErrorInternalServerError("Something went wrong"))?
-        .unwrap();
-
-    log::info!("Row fetched successfully {:?}", val);
-    let otp = Otp::try_from(val).map_err(|e| {
-        error!("Error converting row to Otp: {:?}", e);
-        error::ErrorInternalServerError("Something went wrong")
-    })?;
+        .await?
+        .ok_or_else(|| AppError::not_found("No account registered with this email"))?
+        .get::<String>(0)?;
 
-    if otp.otp == form.otp
-        && otp.email == form.email
-        && otp.created_at.and_utc() + Duration::minutes(5) > chrono::Utc::now()
-    {
-        // WRITE UPDATE QUERY TO MARK USER is_active = true
-        conn.execute(
-            "UPDATE users SET is_active = true WHERE email = ?1",
-            params![form.email.clone()],
-        )
-        .await
-        .map_err(|e| {
-            error!("Error updating user: {:?}", e);
-            error::ErrorInternalServerError("Something went wrong")
-        })?;
-
-        let user_id = conn
-            .query("SELECT id FROM users WHERE email = ?1", params!(form.email))
-            .await
-            .map_err(|e| {
-                error!("Error fetching user: {:?}", e);
-                error::ErrorInternalServerError("Something went wrong")
-            })?
-            .next()
-            .await
-            .map_err(|e| {
-                error!("Error fetching user: {:?}", e);
-                error::ErrorInternalServerError("Something went wrong")
-            })?
-            .unwrap()
-            .get::<String>(0)
-            .unwrap();
-
-        let mut claim = Claims::new(user_id);
-
-        let access_token = claim.get_access(&jwt).map_err(|e| {
-            error!("Error generating access token: {:?}", e);
-            error::ErrorInternalServerError("Something went wrong")
-        })?;
-        let refresh_token = claim.get_refresh(&jwt).map_err(|e| {
-            error!("Error generating refresh token: {:?}", e);
-            error::ErrorInternalServerError("Something went wrong")
-        })?;
-
-        return Ok(HttpResponse::Ok().json(json!({
-            "tokens" : {
-                "access_token": access_token,
-                "refresh_token": refresh_token
-            }
-        })));
+    let tokens = start_session(&user_id, &req, &conn, &jwt).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "tokens": tokens })))
+}
+
+/// Checks an OTP and turns every way it can fail into its own error.
+async fn check_otp(
+    email: &str,
+    purpose: OtpPurpose,
+    code: &str,
+    conn: &Connection,
+) -> Result<(), AppError> {
+    match Otp::verify(email, purpose, code, conn).await? {
+        OtpCheck::Valid => Ok(()),
+        OtpCheck::NotFound => Err(AppError::BadRequest(
+            "otp_not_found",
+            "No OTP has been requested for this email".to_string(),
+        )),
+        OtpCheck::Expired => Err(AppError::BadRequest(
+            "otp_expired",
+            "OTP has expired. Please request a new one".to_string(),
+        )),
+        OtpCheck::Invalid { remaining } => Err(AppError::BadRequest(
+            "otp_invalid",
+            format!("Invalid OTP. {} attempts remaining", remaining),
+        )),
+        OtpCheck::Locked(wait) => Err(otp_locked(wait)),
     }
+}
 
-    Ok(HttpResponse::BadRequest().body("Invalid OTP"))
+fn otp_locked(wait: i64) -> AppError {
+    AppError::TooManyRequests(
+        "otp_locked",
+        format!("Too many wrong attempts. Try again in {} seconds", wait),
+    )
 }
 
 // ================================================== REFRESH THE EXPIRED ACCESS TOKEN ========================================================
@@ -239,54 +309,164 @@ struct RefreshToken {
 
 #[actix_web::post("/refresh")]
 pub async fn refresh_tokens(
+    req: HttpRequest,
     token: Json<RefreshToken>,
-    conn: Data<Connection>,
+    conn: Conn,
     jwt: Data<token::JWT>,
-) -> Result<HttpResponse, actix_web::Error> {
+    cache: Data<RevocationCache>,
+    mailer: Data<Email>,
+) -> Result<HttpResponse, AppError> {
     let refresh = token.into_inner().token;
     let jwt = jwt.into_inner();
     let conn = conn.into_inner();
-    let rclaim: Claims;
-    if let Ok(r) = Claims::decode(&refresh, &jwt) {
-        rclaim = r;
-    } else {
-        return Ok(HttpResponse::Unauthorized().body("Invalid Token in body"));
+
+    let rclaim = refresh_claims(&refresh, &jwt)?;
+
+    // Refresh tokens issued before sessions existed can't be rotated.
+    let family = rclaim.fam.clone().ok_or_else(|| {
+        AppError::Unauthorized(
+            "session_expired",
+            "This session has ended. Please log in again".to_string(),
+        )
+    })?;
+
+    let role = current_role(&rclaim.sub, &conn).await?;
+    let mut claim = Claims::new(rclaim.sub.clone(), family.clone(), role);
+
+    let access_token = claim.get_access(&jwt)?;
+    let refresh_token = claim.get_refresh(&jwt)?;
+
+    let rotation = Session::rotate(
+        &family,
+        &rclaim.sub,
+        &rclaim.jti,
+        &claim.jti,
+        claim.exp as i64,
+        &conn,
+    )
+    .await?;
+
+    match rotation {
+        Rotation::Rotated => Ok(HttpResponse::Ok().json(json!({
+            "tokens" : {
+                "access_token": access_token,
+                "refresh_token": refresh_token
+            }
+        }))),
+        Rotation::Inactive => Err(AppError::Unauthorized(
+            "session_expired",
+            "This session has ended. Please log in again".to_string(),
+        )),
+        Rotation::Reused => {
+            cache.revoke_family(&family);
+            info!("Refresh token reuse detected, revoked session {}", family);
+            send_alert(&rclaim.sub, AlertKind::TokenReused, &req, &conn, &mailer).await;
+            Err(AppError::Unauthorized(
+                "token_reused",
+                "This refresh token was already used. The session has been revoked".to_string(),
+            ))
+        }
     }
+}
+
+/// Decodes a refresh token from a request body.
+fn refresh_claims(refresh: &str, jwt: &JWT) -> Result<Claims, AppError> {
+    let rclaim = Claims::decode(refresh, jwt)?;
 
     if rclaim.token != "refresh" {
-        return Ok(HttpResponse::Unauthorized().body("Use Refresh token"));
+        return Err(AppError::Unauthorized(
+            "wrong_token_type",
+            "Use Refresh token".to_string(),
+        ));
     }
 
-    if let Ok(b) = Claims::is_valid(&refresh, &conn, &jwt).await {
-        if b {
-            return Ok(HttpResponse::Unauthorized().body("Token is blacklisted"));
-        }
-    } else {
-        return Err(error::ErrorInternalServerError("Something went wrong"));
+    Ok(rclaim)
+}
+
+/// The role to put in `user`'s tokens. Deactivated accounts get none.
+async fn current_role(user: &str, conn: &Connection) -> Result<Role, AppError> {
+    User::role_of(user, conn).await?.ok_or_else(|| {
+        AppError::Forbidden(
+            "account_deactivated",
+            "This account has been deactivated".to_string(),
+        )
+    })
+}
+
+/// Opens a new session for `user` and issues its first pair of tokens.
+async fn start_session(
+    user: &str,
+    req: &HttpRequest,
+    conn: &Connection,
+    jwt: &JWT,
+) -> Result<Value, AppError> {
+    let role = current_role(user, conn).await?;
+    let family = Uuid::new_v4().to_string();
+    let mut claim = Claims::new(user.to_string(), family.clone(), role);
+
+    let access_token = claim.get_access(jwt)?;
+    let refresh_token = claim.get_refresh(jwt)?;
+
+    let (user_agent, ip) = client(req);
+
+    CreateSession {
+        id: &family,
+        user,
+        jti: &claim.jti,
+        expires_at: claim.exp as i64,
+        user_agent,
+        ip,
     }
+    .insert_into_db(conn)
+    .await?;
 
-    Claims::blacklist(&refresh, &conn).await.map_err(|e| {
-        error!("Error blacklisting token: {:?}", e);
-        error::ErrorInternalServerError("Something went wrong")
-    })?;
+    Ok(json!({
+        "access_token": access_token,
+        "refresh_token": refresh_token
+    }))
+}
 
-    let mut tokens = Claims::new(rclaim.sub.clone());
+/// The user agent and address a request came from.
+fn client(req: &HttpRequest) -> (Option<String>, Option<String>) {
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string);
+    let ip = req
+        .connection_info()
+        .realip_remote_addr()
+        .map(str::to_string);
+
+    (user_agent, ip)
+}
 
-    let access_token = tokens.get_access(&jwt).map_err(|e| {
-        error!("Error generating access token: {:?}", e);
-        error::ErrorInternalServerError("Something went wrong")
-    })?;
-    let refresh_token = tokens.get_refresh(&jwt).map_err(|e| {
-        error!("Error generating refresh token: {:?}", e);
-        error::ErrorInternalServerError("Something went wrong")
-    })?;
+/// Tells `user` about something that happened to their account. What set
+/// off the alert has already happened, so failing to queue it is only
+/// logged.
+async fn send_alert(
+    user: &str,
+    kind: AlertKind,
+    req: &HttpRequest,
+    conn: &Connection,
+    mailer: &Email,
+) {
+    let (device, ip) = client(req);
+    let alert = SecurityAlert {
+        kind,
+        time: Utc::now().format("%Y-%m-%d %H:%M UTC").to_string(),
+        device,
+        ip,
+    };
 
-    Ok(HttpResponse::Ok().json(json!({
-        "tokens" : {
-            "access_token": access_token,
-            "refresh_token": refresh_token
-        }
-    })))
+    let queued = match Recipient::by_id(user, conn).await {
+        Ok(Some(to)) => mailer.send_security_alert(&to, alert, conn).await,
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = queued {
+        warn!("Could not queue a security alert for {}: {}", user, e);
+    }
 }
 
 // ======================================== LOGIN ENDPOINT ============================================
@@ -299,14 +479,12 @@ pub struct Credentials {
 
 #[actix_web::post("/login")]
 pub async fn login(
+    req: HttpRequest,
     cred: Json<Credentials>,
-    conn: Data<Connection>,
+    conn: Conn,
     jwt: Data<JWT>,
-) -> Result<HttpResponse, actix_web::Error> {
-    cred.validate().map_err(|validation_errors| {
-        info!("User validation failed: {:?}", validation_errors);
-        error::ErrorBadRequest(serde_json::to_string(&validation_errors).unwrap_or_default())
-    })?;
+) -> Result<HttpResponse, AppError> {
+    cred.validate()?;
 
     let cred = cred.into_inner();
     let conn = conn.into_inner();
@@ -316,53 +494,47 @@ pub async fn login(
             "SELECT * FROM users WHERE username = ?1",
             params![cred.user.clone()],
         )
-        .await
-        .map_err(|e| {
-            error!("Error checking if user exists: {:?}", e);
-            error::ErrorInternalServerError("Failed to login user. Please try again.")
-        })?;
-
-    let user = match row.next().await {
-        Ok(Some(user)) => user,
-        _ => return Ok(HttpResponse::BadRequest().body("User not found")),
+        .await?;
+
+    let invalid = || {
+        AppError::Unauthorized(
+            "invalid_credentials",
+            "Invalid username or password".to_string(),
+        )
     };
 
-    let password = user.get::<String>(3).unwrap();
-
-    if let Ok(val) = bcrypt::verify(cred.password, &password) {
-        if val {
-            let user_id = user.get::<String>(0).unwrap();
-            let mut claim = Claims::new(user_id.clone());
-
-            let access_token = claim.get_access(&jwt).map_err(|e| {
-                error!("Error generating access token: {:?}", e);
-                error::ErrorInternalServerError("Something went wrong")
-            })?;
-            let refresh_token = claim.get_refresh(&jwt).map_err(|e| {
-                error!("Error generating refresh token: {:?}", e);
-                error::ErrorInternalServerError("Something went wrong")
-            })?;
-
-            return Ok(HttpResponse::Ok().json(json!({
-                "tokens" : {
-                    "access_token": access_token,
-                    "refresh_token": refresh_token
-                },
-                "user" : {
-                    "id": user_id,
-                    "email": user.get::<String>(9).unwrap(),
-                    "username": user.get::<String>(2).unwrap(),
-                    "first_name": user.get::<String>(4).unwrap(),
-                    "last_name": user.get::<String>(5).unwrap(),
-                    "roll": user.get::<String>(1).unwrap(),
-                    "dob": user.get::<String>(10).unwrap(),
-                    "bio": user.get::<Option<String>>(14).unwrap_or(Some("".to_string())),
-                }
-            })));
-        }
+    let user = row.next().await?.ok_or_else(invalid)?;
+
+    let password = user.get::<String>(3)?;
+
+    if !bcrypt::verify(cred.password, &password)? {
+        return Err(invalid());
     }
 
-    Ok(HttpResponse::BadRequest().body("Invalid credentials"))
+    if !user.get::<Option<bool>>(11)?.unwrap_or(false) {
+        return Err(AppError::Forbidden(
+            "account_not_verified",
+            "Verify your email address before logging in".to_string(),
+        ));
+    }
+
+    let user_id = user.get::<String>(0)?;
+    let tokens = start_session(&user_id, &req, &conn, &jwt).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "tokens" : tokens,
+        "user" : {
+            "id": user_id,
+            "email": user.get::<String>(9)?,
+            "username": user.get::<String>(2)?,
+            "first_name": user.get::<String>(4)?,
+            "last_name": user.get::<String>(5)?,
+            "roll": user.get::<String>(1)?,
+            "dob": user.get::<String>(10)?,
+            "bio": user.get::<Option<String>>(14)?.unwrap_or_default(),
+            "profile_url": user.get::<Option<String>>(13)?,
+        }
+    })))
 }
 
 // ======================================== LOGOUT ENDPOINT ============================================
@@ -371,48 +543,173 @@ pub async fn login(
 async fn logout(
     req: HttpRequest,
     refresh: Json<RefreshToken>,
-    conn: Data<Connection>,
+    conn: Conn,
     jwt: Data<JWT>,
-) -> Result<HttpResponse, actix_web::Error> {
+    cache: Data<RevocationCache>,
+) -> Result<HttpResponse, AppError> {
     let refresh = refresh.into_inner().token;
     let jwt = jwt.into_inner();
     let conn = conn.into_inner();
 
-    let access: Arc<String>;
-    if let Some(t) = req.extensions().get::<Arc<String>>() {
-        access = t.clone();
-    } else {
-        return Ok(HttpResponse::Unauthorized().body("Token not found"));
+    let access = req
+        .extensions()
+        .get::<Arc<String>>()
+        .cloned()
+        .ok_or_else(|| AppError::Unauthorized("missing_token", "Token not found".to_string()))?;
+    let claims = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+
+    let rclaim = refresh_claims(&refresh, &jwt)?;
+    if rclaim.sub != claims.sub {
+        return Err(AppError::Unauthorized(
+            "token_mismatch",
+            "Refresh token belongs to another user".to_string(),
+        ));
     }
 
-    let rclaim: Claims;
-    if let Ok(r) = Claims::decode(&refresh, &jwt) {
-        rclaim = r;
-    } else {
-        return Ok(HttpResponse::Unauthorized().body("Invalid Token in body"));
+    for family in [&claims.fam, &rclaim.fam].into_iter().flatten() {
+        Session::revoke(family, &claims.sub, &conn).await?;
+        cache.revoke_family(family);
     }
 
-    if rclaim.token != "refresh" {
-        return Ok(HttpResponse::Unauthorized().body("Use Refresh token"));
+    rclaim.blacklist(&refresh, &conn).await?;
+    claims.blacklist(&access, &conn).await?;
+    cache.revoke_token(claims.blacklist_key(&access));
+
+    Ok(HttpResponse::Ok().body("Logged out successfully"))
+}
+
+// ======================================== SESSIONS ============================================
+
+#[actix_web::get("/sessions")]
+pub async fn list_sessions(req: HttpRequest, conn: Conn) -> Result<HttpResponse, AppError> {
+    let claims = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+
+    let sessions = Session::retrieve_from_db(&claims.sub, &conn).await?;
+    let sessions: Vec<Value> = sessions
+        .into_iter()
+        .map(|s| {
+            let current = claims.fam.as_deref() == Some(s.id.as_str());
+            let mut session = json!(s);
+            session["current"] = json!(current);
+            session
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json!(sessions)))
+}
+
+#[actix_web::delete("/sessions/{session_id}")]
+pub async fn revoke_session(
+    req: HttpRequest,
+    conn: Conn,
+    cache: Data<RevocationCache>,
+    session_id: Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let claims = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+
+    if !Session::revoke(&session_id, &claims.sub, &conn).await? {
+        return Err(AppError::not_found("Session not found"));
     }
+    cache.revoke_family(&session_id);
 
-    if let Ok(b) = Claims::is_valid(&refresh, &conn, &jwt).await {
-        if !b {
-            return Ok(HttpResponse::Unauthorized().body("Token is blacklisted"));
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Signs out everywhere except the session making the request.
+#[actix_web::delete("/sessions")]
+pub async fn revoke_other_sessions(
+    req: HttpRequest,
+    conn: Conn,
+    cache: Data<RevocationCache>,
+) -> Result<HttpResponse, AppError> {
+    let claims = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+
+    let revoked = Session::revoke_all(&claims.sub, claims.fam.as_deref(), &conn).await?;
+    cache.revoke_user(&claims.sub);
+
+    Ok(HttpResponse::Ok().json(json!({ "revoked": revoked })))
+}
+
+// ======================================== FORGOT PASSWORD ============================================
+
+#[actix_web::post("/forgot-password")]
+pub async fn forgot_password(
+    conn: Conn,
+    email: Json<EmailVerificationForm>,
+    mailer: Data<Email>,
+) -> Result<HttpResponse, AppError> {
+    email.validate()?;
+
+    let email = email.into_inner();
+
+    let mut row = conn
+        .query(
+            "SELECT id FROM users WHERE email = ?1",
+            params![email.email.clone()],
+        )
+        .await?;
+
+    // Answer the same way whether or not the account exists so this can't be
+    // used to find out who is registered.
+    if row.next().await?.is_some() {
+        let to = Recipient::by_email(&email.email, &conn).await?;
+        let otp = Email::generate_otp();
+        let request =
+            Otp::insert_into_db(&email.email, &otp, OtpPurpose::PasswordReset, &conn).await?;
+
+        if request == OtpRequest::Issued {
+            if let Err(e) = mailer.send_password_reset(&to, &otp, &conn).await {
+                Otp::withdraw(&email.email, &otp, OtpPurpose::PasswordReset, &conn).await?;
+                return Err(e);
+            }
         }
-    } else {
-        return Err(error::ErrorInternalServerError("Something went wrong"));
     }
 
-    Claims::blacklist(&refresh, &conn).await.map_err(|e| {
-        error!("Error blacklisting token: {:?}", e);
-        error::ErrorInternalServerError("Something went wrong")
-    })?;
+    Ok(HttpResponse::Ok().body("If an account exists for this email, a reset code has been sent"))
+}
 
-    Claims::blacklist(&access, &conn).await.map_err(|e| {
-        error!("Error blacklisting token: {:?}", e);
-        error::ErrorInternalServerError("Something went wrong")
-    })?;
+// ======================================== RESET PASSWORD ============================================
 
-    Ok(HttpResponse::Ok().body("Logged out successfully"))
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct ResetPasswordForm {
+    #[validate(email(message = "Invalid email address"))]
+    email: String,
+    #[validate(length(min = 6, max = 6, message = "OTP must be 6 characters long"))]
+    otp: String,
+    #[validate(length(min = 8, message = "Password must be at least 8 characters long"))]
+    password: String,
+}
+
+#[actix_web::post("/reset-password")]
+pub async fn reset_password(
+    req: HttpRequest,
+    conn: Conn,
+    cache: Data<RevocationCache>,
+    mailer: Data<Email>,
+    form: Json<ResetPasswordForm>,
+) -> Result<HttpResponse, AppError> {
+    form.validate()?;
+
+    let form = form.into_inner();
+
+    check_otp(&form.email, OtpPurpose::PasswordReset, &form.otp, &conn).await?;
+
+    let Some(user) = User::reset_password(&form.email, &form.password, &conn).await? else {
+        return Err(AppError::not_found("No account registered with this email"));
+    };
+    cache.revoke_user(&user);
+    send_alert(&user, AlertKind::PasswordChanged, &req, &conn, &mailer).await;
+
+    Ok(HttpResponse::Ok().body("Password has been reset. Please log in again"))
+}
+
+// ======================================== PUBLIC KEYS ============================================
+
+/// Lets other services verify our tokens. Keys come and go with rotations,
+/// so caches shouldn't hold on to this for long.
+#[actix_web::get("/jwks.json")]
+pub async fn jwks(jwt: Data<JWT>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "public, max-age=300"))
+        .json(jwt.jwks())
 }
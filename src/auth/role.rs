@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+/// What a user may do. Each role can do everything the ones before it can.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    /// Can remove other people's posts and comments.
+    Moderator,
+    /// Can also manage users and institutions.
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// Fails unless this role includes `required`.
+    pub fn require(&self, required: Role) -> Result<(), AppError> {
+        if *self < required {
+            return Err(AppError::Forbidden(
+                "insufficient_role",
+                format!("This requires the {} role", required.as_str()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<&str> for Role {
+    type Error = AppError;
+
+    fn try_from(role: &str) -> Result<Self, Self::Error> {
+        match role {
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            other => Err(AppError::internal(format!("unknown role {}", other))),
+        }
+    }
+}
@@ -1,93 +1,312 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, Jwk, JwkSet, KeyAlgorithm, PublicKeyUse,
+    RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
 use libsql::{params, Connection};
+use rsa::{
+    pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, traits::PublicKeyParts, RsaPublicKey,
+};
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::{fs, path::Path};
+use uuid::Uuid;
 
+use super::role::Role;
+use crate::config::{JwtConfig, JwtKeys};
+use crate::error::AppError;
+
+/// Key id given to the `private.pem`/`public.pem` pair used before keyrings.
+const LEGACY_KID: &str = "default";
+
+/// The keys tokens are signed and verified with.
+///
+/// New tokens are signed with one key and carry its id in the `kid` header.
+/// Tokens are accepted if any public key in the ring verifies them, so a key
+/// can be replaced without signing everyone out: keep the old public key in
+/// the ring until the last token it signed has expired (7 days), then drop it.
+#[allow(clippy::upper_case_acronyms)]
 pub struct JWT {
+    kid: String,
     private: EncodingKey,
-    public: DecodingKey,
+    public: Vec<(String, DecodingKey)>,
+    jwks: JwkSet,
 }
 
 impl JWT {
-    pub fn init() -> Result<Self, Box<dyn std::error::Error>> {
-        let private = EncodingKey::from_rsa_pem(fs::read_to_string("private.pem")?.as_bytes())?;
-        let public = DecodingKey::from_rsa_pem(fs::read_to_string("public.pem")?.as_bytes())?;
+    /// Loads a keyring directory or a single key pair.
+    ///
+    /// A keyring directory holds `<kid>.pub.pem` for every accepted key and
+    /// `<kid>.key.pem` for the key new tokens are signed with, picked by
+    /// `signing_kid`. That may be left out when only one private key is
+    /// present.
+    pub fn init(config: &JwtConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        match &config.keys {
+            JwtKeys::Dir { dir, signing_kid } => Self::load(dir, signing_kid.as_deref()),
+            JwtKeys::Pair { private, public } => Self::new(
+                (LEGACY_KID, &read_key(private)?),
+                &[(LEGACY_KID.to_string(), read_key(public)?)],
+            ),
+        }
+    }
+
+    pub fn load(dir: &Path, signing: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut private = vec![];
+        let mut public = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if let Some(kid) = name.strip_suffix(".key.pem") {
+                private.push(kid.to_string());
+            } else if let Some(kid) = name.strip_suffix(".pub.pem") {
+                public.push((kid.to_string(), fs::read_to_string(&path)?));
+            }
+        }
+        public.sort();
+
+        let kid = match (signing, private.as_slice()) {
+            (Some(kid), _) => kid.to_string(),
+            (None, [kid]) => kid.clone(),
+            (None, []) => {
+                return Err(format!("No signing key (*.key.pem) in {}", dir.display()).into())
+            }
+            (None, _) => {
+                return Err(format!(
+                    "Several signing keys in {}, set JWT_SIGNING_KID",
+                    dir.display()
+                )
+                .into())
+            }
+        };
+        let key = fs::read_to_string(dir.join(format!("{}.key.pem", kid)))
+            .map_err(|e| format!("Signing key {}: {}", kid, e))?;
+
+        Self::new((&kid, &key), &public)
+    }
+
+    /// Builds a keyring from PEM encoded RSA keys. The signing key's public
+    /// half must be among `public`.
+    pub fn new(
+        signing: (&str, &str),
+        public: &[(String, String)],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (kid, private) = signing;
+        if !public.iter().any(|(id, _)| id == kid) {
+            return Err(format!("No public key for signing key {}", kid).into());
+        }
+
+        let mut keys = vec![];
+        let mut jwks = vec![];
+        for (id, pem) in public {
+            let key = RsaPublicKey::from_public_key_pem(pem)
+                .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+                .map_err(|e| format!("Public key {}: {}", id, e))?;
+            let n = URL_SAFE_NO_PAD.encode(key.n().to_bytes_be());
+            let e = URL_SAFE_NO_PAD.encode(key.e().to_bytes_be());
+
+            keys.push((id.clone(), DecodingKey::from_rsa_components(&n, &e)?));
+            jwks.push(Jwk {
+                common: CommonParameters {
+                    public_key_use: Some(PublicKeyUse::Signature),
+                    key_algorithm: Some(KeyAlgorithm::RS256),
+                    key_id: Some(id.clone()),
+                    ..Default::default()
+                },
+                algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n,
+                    e,
+                }),
+            });
+        }
+
+        Ok(Self {
+            kid: kid.to_string(),
+            private: EncodingKey::from_rsa_pem(private.as_bytes())
+                .map_err(|e| format!("Signing key {}: {}", kid, e))?,
+            public: keys,
+            jwks: JwkSet { keys: jwks },
+        })
+    }
+
+    /// The public keys, for `/.well-known/jwks.json`.
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+
+    fn encode(&self, claims: &Claims) -> Result<String, AppError> {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(self.kid.clone());
+        encode(&header, claims, &self.private).map_err(AppError::internal)
+    }
 
-        Ok(Self { private, public })
+    /// Keys that may have signed `token`. Tokens from before key ids were
+    /// added are tried against every key.
+    fn keys_for(&self, token: &str) -> impl Iterator<Item = &DecodingKey> {
+        let kid = decode_header(token).ok().and_then(|h| h.kid);
+        self.public
+            .iter()
+            .filter(move |(id, _)| kid.as_ref().is_none_or(|kid| kid == id))
+            .map(|(_, key)| key)
     }
 }
 
+fn read_key(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("Key {}: {}", path.display(), e))
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub sub: String,
     pub token: String,
     pub exp: usize,
+    /// When the token was issued, in seconds.
+    #[serde(default)]
+    pub iat: usize,
+    /// When the token was issued, in milliseconds, used to reject tokens
+    /// issued before a password reset. `iat` is too coarse for that.
+    #[serde(default)]
+    pub iat_ms: i64,
+    /// Unique id of this token.
+    #[serde(default)]
+    pub jti: String,
+    /// The session (refresh token family) the token was issued for.
+    #[serde(default)]
+    pub fam: Option<String>,
+    /// The user's role when the token was issued.
+    #[serde(default)]
+    pub role: Role,
 }
 
 impl Claims {
-    pub fn new(uid: String) -> Self {
+    pub fn new(uid: String, family: String, role: Role) -> Self {
         Self {
             sub: uid,
             token: "refresh".to_string(),
             exp: 0,
+            iat: 0,
+            iat_ms: 0,
+            jti: String::new(),
+            fam: Some(family),
+            role,
         }
     }
 
-    pub fn get_access(&mut self, jwt: &JWT) -> Result<String, Box<dyn std::error::Error>> {
-        let expiration = Utc::now()
+    pub fn get_access(&mut self, jwt: &JWT) -> Result<String, AppError> {
+        let now = Utc::now();
+        self.exp = now
             .checked_add_signed(Duration::hours(24))
             .unwrap()
             .timestamp() as usize;
-        self.exp = expiration;
+        self.iat = now.timestamp() as usize;
+        self.iat_ms = now.timestamp_millis();
+        self.jti = Uuid::new_v4().to_string();
         self.token = "access".to_string();
 
-        Ok(encode(&Header::new(Algorithm::RS256), self, &jwt.private)?)
+        jwt.encode(self)
     }
 
-    pub fn get_refresh(&mut self, jwt: &JWT) -> Result<String, Box<dyn std::error::Error>> {
-        let expiration = Utc::now()
+    pub fn get_refresh(&mut self, jwt: &JWT) -> Result<String, AppError> {
+        let now = Utc::now();
+        self.exp = now
             .checked_add_signed(Duration::days(7))
             .unwrap()
             .timestamp() as usize;
-        self.exp = expiration;
+        self.iat = now.timestamp() as usize;
+        self.iat_ms = now.timestamp_millis();
+        self.jti = Uuid::new_v4().to_string();
         self.token = "refresh".to_string();
 
-        Ok(encode(&Header::new(Algorithm::RS256), self, &jwt.private)?)
+        jwt.encode(self)
     }
 
-    pub fn decode(token: &str, jwt: &JWT) -> Result<Self, Box<dyn std::error::Error>> {
-        let data = decode::<Self>(token, &jwt.public, &Validation::new(Algorithm::RS256))?;
-        Ok(data.claims)
+    pub fn decode(token: &str, jwt: &JWT) -> Result<Self, AppError> {
+        let validation = Validation::new(Algorithm::RS256);
+        jwt.keys_for(token)
+            .find_map(|key| decode::<Self>(token, key, &validation).ok())
+            .map(|data| data.claims)
+            .ok_or_else(|| AppError::Unauthorized("invalid_token", "Invalid token".to_string()))
+    }
+
+    /// Tokens issued before `iat_ms` existed count from the start of their
+    /// second, so they never outlive a revocation later in that second.
+    fn issued_at_ms(&self) -> i64 {
+        if self.iat_ms > 0 {
+            self.iat_ms
+        } else {
+            self.iat as i64 * 1000
+        }
     }
 
     pub fn is_expired(&self) -> bool {
         self.exp < chrono::Utc::now().timestamp() as usize
     }
 
-    pub async fn blacklist(
-        token: &str,
-        conn: &Connection,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        conn.execute("INSERT INTO tokens (token) VALUES (?1)", params![token])
-            .await?;
+    /// Identifies `token`, whose claims are `self`, in the blacklist and the
+    /// revocation cache. Tokens issued before they carried a `jti` are keyed
+    /// by the whole token instead.
+    pub fn blacklist_key<'a>(&'a self, token: &'a str) -> &'a str {
+        if self.jti.is_empty() {
+            token
+        } else {
+            &self.jti
+        }
+    }
+
+    /// Revokes `token`, whose claims are `self`, until it expires.
+    pub async fn blacklist(&self, token: &str, conn: &Connection) -> Result<(), AppError> {
+        conn.execute(
+            "INSERT OR IGNORE INTO tokens (jti, expires_at) VALUES (?1, ?2)",
+            params![self.blacklist_key(token), self.exp as i64],
+        )
+        .await?;
         Ok(())
     }
 
-    pub async fn is_valid(
-        token: &str,
-        conn: &Connection,
-        jwt: &JWT,
-    ) -> Result<bool, Box<dyn std::error::Error>> {
-        let mut row = conn
-            .query("SELECT * FROM tokens WHERE token = ?1", params![token])
+    /// Deletes blacklist entries for tokens that have expired anyway.
+    pub async fn prune_blacklist(conn: &Connection) -> Result<u64, AppError> {
+        let pruned = conn
+            .execute(
+                "DELETE FROM tokens WHERE expires_at < ?1",
+                params![Utc::now().timestamp()],
+            )
             .await?;
-        if row.next().await.unwrap().is_some() {
+        Ok(pruned)
+    }
+
+    /// Checks `token`, already decoded into `self`, against the database: it
+    /// must not be blacklisted, predate a password reset or role change, or
+    /// belong to a revoked session or deactivated account.
+    pub async fn is_valid(&self, token: &str, conn: &Connection) -> Result<bool, AppError> {
+        if self.is_expired() {
             return Ok(false);
         }
 
-        let claim = Claims::decode(token, jwt)?;
+        // Tokens from before sessions existed have no family to check.
+        let mut row = conn
+            .query(
+                r#"
+                SELECT users.tokens_valid_after,
+                    ?2 IS NULL OR EXISTS(SELECT 1 FROM sessions WHERE id = ?2 AND revoked_at IS NULL),
+                    NOT EXISTS(SELECT 1 FROM tokens WHERE jti = ?3)
+                FROM users WHERE id = ?1 AND deactivated_at IS NULL
+                "#,
+                params![self.sub.clone(), self.fam.clone(), self.blacklist_key(token)],
+            )
+            .await?;
 
-        return Ok(!claim.is_expired());
+        Ok(match row.next().await? {
+            Some(user) => {
+                user.get::<Option<i64>>(0)?.unwrap_or(0) < self.issued_at_ms()
+                    && user.get::<bool>(1)?
+                    && user.get::<bool>(2)?
+            }
+            None => false,
+        })
     }
 }
@@ -0,0 +1,537 @@
+use std::{env, fmt, fs, path::PathBuf, str::FromStr, time::Duration};
+
+use lettre::message::Mailbox;
+use serde::Serialize;
+
+use crate::auth::{cache, domains::DomainPolicy};
+
+/// A value that must not end up in logs.
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[redacted]")
+    }
+}
+
+/// Settings for every subsystem, read once at startup.
+///
+/// Each setting is read from an environment variable. If `ONCAMPUS_CONFIG`
+/// names a TOML file, settings in it take precedence; there they live under
+/// the section and key given next to each variable below, e.g.
+///
+/// ```toml
+/// [server]
+/// bind = "0.0.0.0:8080"
+///
+/// [database]
+/// url = "libsql://oncampus.turso.io"
+/// ```
+#[derive(Debug)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub email: EmailConfig,
+    pub jwt: JwtConfig,
+    pub auth: AuthConfig,
+    pub storage: StorageConfig,
+}
+
+#[derive(Debug)]
+pub struct ServerConfig {
+    /// `BIND_ADDRESS`, `server.bind`.
+    pub bind: String,
+}
+
+/// `DB_MODE`, `database.mode`: `replica`, `local` or `memory`.
+#[derive(Debug)]
+pub enum DatabaseConfig {
+    /// A local copy of the remote primary, kept in sync in the background.
+    Replica {
+        /// `DB_DCRUST_URL`, `database.url`.
+        url: String,
+        /// `DB_DCRUST_TOKEN`, `database.token`.
+        token: Secret,
+        /// `DB_REPLICA_PATH`, `database.replica_path`, default `local.db`.
+        path: PathBuf,
+        /// `DB_SYNC_INTERVAL_SECS`, `database.sync_interval_secs`.
+        sync_interval: Duration,
+    },
+    /// A plain SQLite file, for working offline. It has its own default
+    /// path so it is never mistaken for a replica.
+    Local {
+        /// `DB_PATH`, `database.path`, default `offline.db`.
+        path: PathBuf,
+    },
+    /// Starts empty and is lost when the server stops.
+    Memory,
+}
+
+#[derive(Debug)]
+pub struct EmailConfig {
+    /// `EMAIL_FROM`, `email.from`, e.g. `OnCampus <oncampus.chat@gmail.com>`.
+    pub from: Mailbox,
+    pub transport: MailTransport,
+    pub branding: Branding,
+    /// `OUTBOX_RETENTION_DAYS`, `email.outbox_retention_days`: how long sent
+    /// and failed mail stays in the outbox.
+    pub outbox_retention_days: i64,
+}
+
+/// How emails present the app. Every template can use these as `brand.*`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Branding {
+    /// `BRAND_NAME`, `email.brand_name`.
+    pub name: String,
+    /// `BRAND_COLOR`, `email.brand_color`, for headings and buttons.
+    pub color: String,
+    /// `BRAND_LOGO_URL`, `email.logo_url`. The name is shown without one.
+    pub logo_url: Option<String>,
+    /// `BRAND_URL`, `email.brand_url`, linked from the footer.
+    pub url: Option<String>,
+    /// `SUPPORT_EMAIL`, `email.support_email`.
+    pub support_email: Option<String>,
+}
+
+/// `MAIL_TRANSPORT`, `email.transport`: `smtp`, `file` or `memory`.
+#[derive(Debug)]
+pub enum MailTransport {
+    Smtp {
+        /// `SMTP_RELAY`, `email.relay`.
+        host: String,
+        /// `SMTP_PORT`, `email.port`. Defaults to the usual port for `tls`.
+        port: Option<u16>,
+        /// `SMTP_TLS`, `email.tls`.
+        tls: SmtpTls,
+        /// `EMAIL`/`email.username` and `EMAIL_APP_PASSWORD`/`email.password`.
+        /// Mail is sent without logging in when no username is set.
+        credentials: Option<(String, Secret)>,
+    },
+    /// Writes each message to a `.eml` file in `MAIL_DIR`/`email.dir`.
+    File { dir: PathBuf },
+    /// Keeps messages in memory without sending them.
+    Memory,
+}
+
+/// `starttls`, `tls` or `none`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    /// Upgrade a plain connection, port 587 by default.
+    StartTls,
+    /// TLS from the start, port 465 by default.
+    Tls,
+    /// Unencrypted, port 25 by default. Only for local relays.
+    None,
+}
+
+impl FromStr for SmtpTls {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "starttls" => Ok(SmtpTls::StartTls),
+            "tls" => Ok(SmtpTls::Tls),
+            "none" => Ok(SmtpTls::None),
+            other => Err(format!(
+                "expected `starttls`, `tls` or `none`, got `{}`",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JwtConfig {
+    pub keys: JwtKeys,
+    /// `TOKEN_CACHE_CAPACITY`, `jwt.cache_capacity`.
+    pub cache_capacity: usize,
+    /// `TOKEN_CACHE_TTL_SECS`, `jwt.cache_ttl_secs`.
+    pub cache_ttl: Duration,
+}
+
+/// Where the token signing keys come from.
+#[derive(Debug)]
+pub enum JwtKeys {
+    /// A single key pair, `JWT_PRIVATE_KEY`/`jwt.private_key` and
+    /// `JWT_PUBLIC_KEY`/`jwt.public_key`.
+    Pair { private: PathBuf, public: PathBuf },
+    /// A keyring directory, `JWT_KEY_DIR`/`jwt.key_dir`, signing with
+    /// `JWT_SIGNING_KID`/`jwt.signing_kid`.
+    Dir {
+        dir: PathBuf,
+        signing_kid: Option<String>,
+    },
+}
+
+#[derive(Debug)]
+pub struct AuthConfig {
+    /// `ALLOWED_EMAIL_DOMAINS`, `auth.allowed_email_domains`. Only adds and
+    /// updates institutions; removing a domain here doesn't close it.
+    pub domains: DomainPolicy,
+    /// `UNVERIFIED_ACCOUNT_DAYS`, `auth.unverified_account_days`.
+    pub unverified_account_days: i64,
+}
+
+/// `STORAGE_BACKEND`, `storage.backend`.
+#[derive(Debug)]
+pub enum StorageConfig {
+    Local {
+        /// `LOCAL_STORAGE_DIR`, `storage.local_dir`.
+        dir: PathBuf,
+        /// `PUBLIC_URL`, `storage.public_url`.
+        base_url: String,
+    },
+    S3 {
+        /// `AWS_ACCESS_KEY_ID`, `storage.access_key_id`.
+        access_key_id: String,
+        /// `AWS_SECRET_ACCESS_KEY`, `storage.secret_access_key`.
+        secret_access_key: Secret,
+        /// `AWS_REGION`, `storage.region`.
+        region: String,
+        /// `AWS_BUCKET`, `storage.bucket`.
+        bucket: String,
+    },
+}
+
+pub enum ConfigError {
+    Missing {
+        env: &'static str,
+        key: &'static str,
+    },
+    Invalid {
+        env: &'static str,
+        key: &'static str,
+        reason: String,
+    },
+    File {
+        path: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing { env, key } => write!(
+                f,
+                "Missing setting: set {} or `{}` in the config file",
+                env, key
+            ),
+            ConfigError::Invalid { env, key, reason } => {
+                write!(f, "Invalid setting {} (`{}`): {}", env, key, reason)
+            }
+            ConfigError::File { path, reason } => {
+                write!(f, "Could not read config file {}: {}", path, reason)
+            }
+        }
+    }
+}
+
+// `main` returning an error prints it with `Debug`, so make that readable too.
+impl fmt::Debug for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Reads the environment and the file named in `ONCAMPUS_CONFIG`, if any.
+    pub fn load() -> Result<Self, ConfigError> {
+        let file = match env::var("ONCAMPUS_CONFIG") {
+            Ok(path) if !path.is_empty() => Some(read_file(&path)?),
+            _ => None,
+        };
+        Self::from_source(&Source { file })
+    }
+
+    fn from_source(src: &Source) -> Result<Self, ConfigError> {
+        let server = ServerConfig {
+            bind: src.or("BIND_ADDRESS", "server.bind", "127.0.0.1:8080")?,
+        };
+
+        let database = match src.or("DB_MODE", "database.mode", "replica")?.as_str() {
+            "replica" => DatabaseConfig::Replica {
+                url: src.required("DB_DCRUST_URL", "database.url")?,
+                token: Secret(src.required("DB_DCRUST_TOKEN", "database.token")?),
+                path: src
+                    .or("DB_REPLICA_PATH", "database.replica_path", "local.db")?
+                    .into(),
+                sync_interval: Duration::from_secs(src.parse(
+                    "DB_SYNC_INTERVAL_SECS",
+                    "database.sync_interval_secs",
+                    30,
+                )?),
+            },
+            "local" => DatabaseConfig::Local {
+                path: src.or("DB_PATH", "database.path", "offline.db")?.into(),
+            },
+            "memory" => DatabaseConfig::Memory,
+            other => {
+                return Err(ConfigError::Invalid {
+                    env: "DB_MODE",
+                    key: "database.mode",
+                    reason: format!("expected `replica`, `local` or `memory`, got `{}`", other),
+                })
+            }
+        };
+
+        let transport = match src
+            .or("MAIL_TRANSPORT", "email.transport", "smtp")?
+            .as_str()
+        {
+            "smtp" => MailTransport::Smtp {
+                host: src.or("SMTP_RELAY", "email.relay", "smtp.gmail.com")?,
+                port: src.parse_optional("SMTP_PORT", "email.port")?,
+                tls: src.parse_str("SMTP_TLS", "email.tls", "starttls")?,
+                credentials: match src.optional("EMAIL", "email.username")? {
+                    Some(username) => Some((
+                        username,
+                        Secret(src.required("EMAIL_APP_PASSWORD", "email.password")?),
+                    )),
+                    None => None,
+                },
+            },
+            "file" => MailTransport::File {
+                dir: src.or("MAIL_DIR", "email.dir", "mail")?.into(),
+            },
+            "memory" => MailTransport::Memory,
+            other => {
+                return Err(ConfigError::Invalid {
+                    env: "MAIL_TRANSPORT",
+                    key: "email.transport",
+                    reason: format!("expected `smtp`, `file` or `memory`, got `{}`", other),
+                })
+            }
+        };
+        let email = EmailConfig {
+            from: src.parse_str(
+                "EMAIL_FROM",
+                "email.from",
+                "OnCampus <oncampus.chat@gmail.com>",
+            )?,
+            transport,
+            branding: Branding {
+                name: src.or("BRAND_NAME", "email.brand_name", "OnCampus")?,
+                color: src.or("BRAND_COLOR", "email.brand_color", "#2563eb")?,
+                logo_url: src.optional("BRAND_LOGO_URL", "email.logo_url")?,
+                url: src.optional("BRAND_URL", "email.brand_url")?,
+                support_email: src.optional("SUPPORT_EMAIL", "email.support_email")?,
+            },
+            outbox_retention_days: src.parse(
+                "OUTBOX_RETENTION_DAYS",
+                "email.outbox_retention_days",
+                7,
+            )?,
+        };
+
+        let keys = match src.optional("JWT_KEY_DIR", "jwt.key_dir")? {
+            Some(dir) => JwtKeys::Dir {
+                dir: dir.into(),
+                signing_kid: src.optional("JWT_SIGNING_KID", "jwt.signing_kid")?,
+            },
+            None => JwtKeys::Pair {
+                private: src
+                    .or("JWT_PRIVATE_KEY", "jwt.private_key", "private.pem")?
+                    .into(),
+                public: src
+                    .or("JWT_PUBLIC_KEY", "jwt.public_key", "public.pem")?
+                    .into(),
+            },
+        };
+        let jwt = JwtConfig {
+            keys,
+            cache_capacity: src.parse(
+                "TOKEN_CACHE_CAPACITY",
+                "jwt.cache_capacity",
+                cache::DEFAULT_CAPACITY,
+            )?,
+            cache_ttl: Duration::from_secs(src.parse(
+                "TOKEN_CACHE_TTL_SECS",
+                "jwt.cache_ttl_secs",
+                cache::DEFAULT_TTL.as_secs(),
+            )?),
+        };
+
+        let domains = src.or(
+            "ALLOWED_EMAIL_DOMAINS",
+            "auth.allowed_email_domains",
+            "dcrustm.org",
+        )?;
+        let auth = AuthConfig {
+            domains: DomainPolicy::parse(&domains).map_err(|e| ConfigError::Invalid {
+                env: "ALLOWED_EMAIL_DOMAINS",
+                key: "auth.allowed_email_domains",
+                reason: e.to_string(),
+            })?,
+            unverified_account_days: src.parse(
+                "UNVERIFIED_ACCOUNT_DAYS",
+                "auth.unverified_account_days",
+                7,
+            )?,
+        };
+
+        let storage = match src
+            .or("STORAGE_BACKEND", "storage.backend", "local")?
+            .as_str()
+        {
+            "local" => StorageConfig::Local {
+                dir: src
+                    .or("LOCAL_STORAGE_DIR", "storage.local_dir", "media")?
+                    .into(),
+                base_url: src.or("PUBLIC_URL", "storage.public_url", "http://127.0.0.1:8080")?,
+            },
+            "s3" => StorageConfig::S3 {
+                access_key_id: src.required("AWS_ACCESS_KEY_ID", "storage.access_key_id")?,
+                secret_access_key: Secret(
+                    src.required("AWS_SECRET_ACCESS_KEY", "storage.secret_access_key")?,
+                ),
+                region: src.required("AWS_REGION", "storage.region")?,
+                bucket: src.required("AWS_BUCKET", "storage.bucket")?,
+            },
+            other => {
+                return Err(ConfigError::Invalid {
+                    env: "STORAGE_BACKEND",
+                    key: "storage.backend",
+                    reason: format!("expected `local` or `s3`, got `{}`", other),
+                })
+            }
+        };
+
+        Ok(Self {
+            server,
+            database,
+            email,
+            jwt,
+            auth,
+            storage,
+        })
+    }
+}
+
+fn read_file(path: &str) -> Result<toml::Table, ConfigError> {
+    let error = |reason: String| ConfigError::File {
+        path: path.to_string(),
+        reason,
+    };
+    let text = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    text.parse::<toml::Table>()
+        .map_err(|e| error(e.message().to_string()))
+}
+
+/// The environment, with the config file layered on top.
+struct Source {
+    file: Option<toml::Table>,
+}
+
+impl Source {
+    /// Empty values count as unset.
+    fn optional(
+        &self,
+        env: &'static str,
+        key: &'static str,
+    ) -> Result<Option<String>, ConfigError> {
+        if let Some(value) = self.in_file(env, key)? {
+            return Ok(Some(value));
+        }
+        Ok(env::var(env).ok().filter(|v| !v.is_empty()))
+    }
+
+    fn in_file(&self, env: &'static str, key: &'static str) -> Result<Option<String>, ConfigError> {
+        let Some(file) = &self.file else {
+            return Ok(None);
+        };
+        let (section, name) = key.split_once('.').unwrap_or(("", key));
+        let value = match file.get(section).and_then(|s| s.get(name)) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        Ok(Some(match value {
+            toml::Value::String(s) => s.clone(),
+            toml::Value::Integer(i) => i.to_string(),
+            toml::Value::Float(f) => f.to_string(),
+            toml::Value::Boolean(b) => b.to_string(),
+            _ => {
+                return Err(ConfigError::Invalid {
+                    env,
+                    key,
+                    reason: "expected a string or a number".to_string(),
+                })
+            }
+        })
+        .filter(|v| !v.is_empty()))
+    }
+
+    fn required(&self, env: &'static str, key: &'static str) -> Result<String, ConfigError> {
+        self.optional(env, key)?
+            .ok_or(ConfigError::Missing { env, key })
+    }
+
+    fn or(
+        &self,
+        env: &'static str,
+        key: &'static str,
+        default: &str,
+    ) -> Result<String, ConfigError> {
+        Ok(self
+            .optional(env, key)?
+            .unwrap_or_else(|| default.to_string()))
+    }
+
+    fn parse<T>(&self, env: &'static str, key: &'static str, default: T) -> Result<T, ConfigError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        Ok(self.parse_optional(env, key)?.unwrap_or(default))
+    }
+
+    fn parse_optional<T>(
+        &self,
+        env: &'static str,
+        key: &'static str,
+    ) -> Result<Option<T>, ConfigError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.optional(env, key)?
+            .map(|value| {
+                value.parse().map_err(|e: T::Err| ConfigError::Invalid {
+                    env,
+                    key,
+                    reason: e.to_string(),
+                })
+            })
+            .transpose()
+    }
+
+    /// Like `parse` with a default that is parsed too.
+    fn parse_str<T>(
+        &self,
+        env: &'static str,
+        key: &'static str,
+        default: &str,
+    ) -> Result<T, ConfigError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.or(env, key, default)?
+            .parse()
+            .map_err(|e: T::Err| ConfigError::Invalid {
+                env,
+                key,
+                reason: e.to_string(),
+            })
+    }
+}
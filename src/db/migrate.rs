@@ -0,0 +1,131 @@
+use std::fmt;
+
+/// A single numbered schema change. `up` moves the schema to `version`,
+/// `down` moves it back to `version - 1`.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+/// Every migration known to this binary, in the order they must be applied.
+/// New migrations are appended here with the next version number and never
+/// edited once released.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        up: include_str!("migrations/0001_initial_schema.up.sql"),
+        down: include_str!("migrations/0001_initial_schema.down.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "post_edits",
+        up: include_str!("migrations/0002_post_edits.up.sql"),
+        down: include_str!("migrations/0002_post_edits.down.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "unique_post_likes",
+        up: include_str!("migrations/0003_unique_post_likes.up.sql"),
+        down: include_str!("migrations/0003_unique_post_likes.down.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "comment_replies",
+        up: include_str!("migrations/0004_comment_replies.up.sql"),
+        down: include_str!("migrations/0004_comment_replies.down.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "password_reset",
+        up: include_str!("migrations/0005_password_reset.up.sql"),
+        down: include_str!("migrations/0005_password_reset.down.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "otp_attempts",
+        up: include_str!("migrations/0006_otp_attempts.up.sql"),
+        down: include_str!("migrations/0006_otp_attempts.down.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "institutions",
+        up: include_str!("migrations/0007_institutions.up.sql"),
+        down: include_str!("migrations/0007_institutions.down.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "sessions",
+        up: include_str!("migrations/0008_sessions.up.sql"),
+        down: include_str!("migrations/0008_sessions.down.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "token_blacklist_expiry",
+        up: include_str!("migrations/0009_token_blacklist_expiry.up.sql"),
+        down: include_str!("migrations/0009_token_blacklist_expiry.down.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "roles",
+        up: include_str!("migrations/0010_roles.up.sql"),
+        down: include_str!("migrations/0010_roles.down.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "email_outbox",
+        up: include_str!("migrations/0011_email_outbox.up.sql"),
+        down: include_str!("migrations/0011_email_outbox.down.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "user_locale",
+        up: include_str!("migrations/0012_user_locale.up.sql"),
+        down: include_str!("migrations/0012_user_locale.down.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "tokens_valid_after_ms",
+        up: include_str!("migrations/0013_tokens_valid_after_ms.up.sql"),
+        down: include_str!("migrations/0013_tokens_valid_after_ms.down.sql"),
+    },
+];
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+#[derive(Debug)]
+pub enum MigrateError {
+    Db(libsql::Error),
+    /// The database has migrations applied that this binary does not know about.
+    DatabaseAhead {
+        database: i64,
+        binary: i64,
+    },
+    UnknownVersion(i64),
+}
+
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrateError::Db(e) => write!(f, "migration failed: {}", e),
+            MigrateError::DatabaseAhead { database, binary } => write!(
+                f,
+                "database schema is at version {} but this binary only knows up to {}; refusing to start",
+                database, binary
+            ),
+            MigrateError::UnknownVersion(v) => write!(f, "unknown schema version {}", v),
+        }
+    }
+}
+
+impl std::error::Error for MigrateError {}
+
+impl From<libsql::Error> for MigrateError {
+    fn from(e: libsql::Error) -> Self {
+        MigrateError::Db(e)
+    }
+}
@@ -0,0 +1,13 @@
+DROP INDEX IF EXISTS idx_id;
+DROP INDEX IF EXISTS idx_email;
+DROP INDEX IF EXISTS idx_follower_id;
+DROP INDEX IF EXISTS idx_followed_id;
+
+DROP TABLE IF EXISTS tokens;
+DROP TABLE IF EXISTS otps;
+DROP TABLE IF EXISTS post_comments;
+DROP TABLE IF EXISTS post_likes;
+DROP TABLE IF EXISTS post_images;
+DROP TABLE IF EXISTS posts;
+DROP TABLE IF EXISTS followers;
+DROP TABLE IF EXISTS users;
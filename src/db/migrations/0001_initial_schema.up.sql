@@ -0,0 +1,79 @@
+CREATE TABLE IF NOT EXISTS users (
+    id TEXT PRIMARY KEY,
+    roll TEXT NOT NULL UNIQUE,
+    username TEXT NOT NULL UNIQUE,
+    password TEXT NOT NULL,
+    first_name TEXT NOT NULL,
+    last_name TEXT NOT NULL,
+    posts INTEGER DEFAULT 0,
+    followers INTEGER DEFAULT 0,
+    following INTEGER DEFAULT 0,
+    email TEXT NOT NULL UNIQUE,
+    dob DATE NOT NULL,
+    is_active BOOLEAN DEFAULT FALSE,
+    is_superuser BOOLEAN DEFAULT FALSE,
+    profile_url TEXT,
+    bio TEXT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS followers (
+    follower_id TEXT NOT NULL,
+    followed_id TEXT NOT NULL,
+    PRIMARY KEY (follower_id, followed_id),
+    FOREIGN KEY (follower_id) REFERENCES users (id) ON DELETE CASCADE,
+    FOREIGN KEY (followed_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS posts (
+    id TEXT PRIMARY KEY,
+    user TEXT NOT NULL,
+    text TEXT,
+    public BOOLEAN DEFAULT TRUE,
+    likes INTEGER DEFAULT 0,
+    comments INTEGER DEFAULT 0,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS post_images (
+    id TEXT PRIMARY KEY,
+    post TEXT NOT NULL,
+    image_url TEXT NOT NULL,
+    FOREIGN KEY (post) REFERENCES posts (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS post_likes (
+    id TEXT PRIMARY KEY,
+    post TEXT NOT NULL,
+    user TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (post) REFERENCES posts (id) ON DELETE CASCADE,
+    FOREIGN KEY (user) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS post_comments (
+    id TEXT PRIMARY KEY,
+    post TEXT NOT NULL,
+    user TEXT NOT NULL,
+    text TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (post) REFERENCES posts (id) ON DELETE CASCADE,
+    FOREIGN KEY (user) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_follower_id ON followers (follower_id);
+CREATE INDEX IF NOT EXISTS idx_followed_id ON followers (followed_id);
+
+CREATE TABLE IF NOT EXISTS otps (
+    email TEXT PRIMARY KEY,
+    otp TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_email ON otps (email);
+CREATE INDEX IF NOT EXISTS idx_id ON users (id);
+
+CREATE TABLE IF NOT EXISTS tokens (
+    token TEXT PRIMARY KEY
+);
@@ -0,0 +1,4 @@
+ALTER TABLE posts ADD COLUMN edited_at TIMESTAMP;
+
+-- users.posts was never incremented on post creation; bring it in line.
+UPDATE users SET posts = (SELECT COUNT(*) FROM posts WHERE posts.user = users.id);
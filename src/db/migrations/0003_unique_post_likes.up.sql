@@ -0,0 +1,7 @@
+DELETE FROM post_likes
+WHERE rowid NOT IN (SELECT MIN(rowid) FROM post_likes GROUP BY post, user);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_post_likes_post_user ON post_likes (post, user);
+
+-- Duplicate likes inflated the counter; recount from the surviving rows.
+UPDATE posts SET likes = (SELECT COUNT(*) FROM post_likes WHERE post_likes.post = posts.id);
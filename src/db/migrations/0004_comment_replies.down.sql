@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS idx_post_comments_parent_id;
+
+DELETE FROM post_comments WHERE parent_id IS NOT NULL;
+UPDATE posts SET comments = (SELECT COUNT(*) FROM post_comments WHERE post_comments.post = posts.id);
+
+ALTER TABLE post_comments DROP COLUMN edited_at;
+ALTER TABLE post_comments DROP COLUMN replies;
+ALTER TABLE post_comments DROP COLUMN parent_id;
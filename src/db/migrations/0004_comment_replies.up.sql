@@ -0,0 +1,5 @@
+ALTER TABLE post_comments ADD COLUMN parent_id TEXT;
+ALTER TABLE post_comments ADD COLUMN replies INTEGER DEFAULT 0;
+ALTER TABLE post_comments ADD COLUMN edited_at TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS idx_post_comments_parent_id ON post_comments (parent_id);
@@ -0,0 +1,14 @@
+ALTER TABLE users DROP COLUMN tokens_valid_after;
+
+CREATE TABLE otps_old (
+    email TEXT PRIMARY KEY,
+    otp TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO otps_old (email, otp, created_at)
+SELECT email, otp, created_at FROM otps WHERE purpose = 'registration';
+DROP TABLE otps;
+ALTER TABLE otps_old RENAME TO otps;
+
+CREATE INDEX IF NOT EXISTS idx_email ON otps (email);
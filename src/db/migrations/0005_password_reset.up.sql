@@ -0,0 +1,16 @@
+-- OTPs are keyed by (email, purpose) so that a code sent for one flow can't
+-- be used for another.
+CREATE TABLE otps_new (
+    email TEXT NOT NULL,
+    purpose TEXT NOT NULL DEFAULT 'registration',
+    otp TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (email, purpose)
+);
+
+INSERT INTO otps_new (email, otp, created_at) SELECT email, otp, created_at FROM otps;
+DROP TABLE otps;
+ALTER TABLE otps_new RENAME TO otps;
+
+-- Unix time before which tokens issued to the user are no longer accepted.
+ALTER TABLE users ADD COLUMN tokens_valid_after INTEGER DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE otps DROP COLUMN locked_until;
+ALTER TABLE otps DROP COLUMN attempts;
@@ -0,0 +1,4 @@
+-- Wrong guesses against the current code, and the unix time until which the
+-- code is locked after too many of them.
+ALTER TABLE otps ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE otps ADD COLUMN locked_until INTEGER;
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_users_institution_id;
+ALTER TABLE users DROP COLUMN institution_id;
+DROP TABLE IF EXISTS institutions;
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS institutions (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- Users whose email is on this domain or any of its subdomains belong here.
+    domain TEXT NOT NULL UNIQUE,
+    roll_format TEXT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TABLE users ADD COLUMN institution_id TEXT REFERENCES institutions (id);
+
+CREATE INDEX IF NOT EXISTS idx_users_institution_id ON users (institution_id);
@@ -0,0 +1,17 @@
+-- One row per login. Every refresh token carries the session id as its token
+-- family; only the most recently issued refresh token (`current_jti`) may be
+-- exchanged, and presenting an older one revokes the whole session.
+CREATE TABLE IF NOT EXISTS sessions (
+    id TEXT PRIMARY KEY,
+    user TEXT NOT NULL,
+    current_jti TEXT NOT NULL,
+    user_agent TEXT,
+    ip TEXT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at INTEGER NOT NULL,
+    revoked_at TIMESTAMP,
+    FOREIGN KEY (user) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_user ON sessions (user);
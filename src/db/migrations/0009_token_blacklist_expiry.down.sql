@@ -0,0 +1,10 @@
+DROP INDEX IF EXISTS idx_tokens_expires_at;
+
+CREATE TABLE tokens_old (
+    token TEXT PRIMARY KEY
+);
+
+INSERT INTO tokens_old (token) SELECT jti FROM tokens;
+
+DROP TABLE tokens;
+ALTER TABLE tokens_old RENAME TO tokens;
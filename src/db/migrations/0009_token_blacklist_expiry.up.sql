@@ -0,0 +1,16 @@
+-- Blacklist entries are keyed by the token's `jti` and remember when the token
+-- expires, so they can be deleted once the token is no longer usable anyway.
+CREATE TABLE tokens_new (
+    jti TEXT PRIMARY KEY,
+    expires_at INTEGER NOT NULL
+);
+
+-- Older entries hold whole tokens that have no `jti`. Keep them, keyed by the
+-- token itself, for as long as a token of that era could have lived.
+INSERT INTO tokens_new (jti, expires_at)
+SELECT token, CAST(strftime('%s', 'now') AS INTEGER) + 7 * 24 * 60 * 60 FROM tokens;
+
+DROP TABLE tokens;
+ALTER TABLE tokens_new RENAME TO tokens;
+
+CREATE INDEX IF NOT EXISTS idx_tokens_expires_at ON tokens (expires_at);
@@ -0,0 +1,7 @@
+-- `is_superuser` is kept in step with `role = 'admin'` for older tooling.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user'
+    CHECK (role IN ('user', 'moderator', 'admin'));
+
+UPDATE users SET role = 'admin' WHERE is_superuser = TRUE;
+
+ALTER TABLE users ADD COLUMN deactivated_at TIMESTAMP;
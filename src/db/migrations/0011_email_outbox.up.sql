@@ -0,0 +1,21 @@
+-- Mail waiting to be sent, kept after delivery so failures can be inspected.
+-- `message` is the complete RFC 5322 message. A worker claims rows whose
+-- `next_attempt_at` has passed and pushes it back on every failure until
+-- the row is given up on with `failed_at`.
+CREATE TABLE IF NOT EXISTS email_outbox (
+    id TEXT PRIMARY KEY,
+    sender TEXT NOT NULL,
+    recipients TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    message BLOB NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL,
+    last_error TEXT,
+    sent_at TIMESTAMP,
+    failed_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_email_outbox_due
+    ON email_outbox (next_attempt_at)
+    WHERE sent_at IS NULL AND failed_at IS NULL;
@@ -0,0 +1,2 @@
+-- The language emails are written in, e.g. `en` or `hi`.
+ALTER TABLE users ADD COLUMN locale TEXT NOT NULL DEFAULT 'en';
@@ -0,0 +1 @@
+UPDATE users SET tokens_valid_after = tokens_valid_after / 1000;
@@ -0,0 +1,3 @@
+-- `tokens_valid_after` is now in milliseconds so that a token issued earlier
+-- in the same second as a revocation is rejected too.
+UPDATE users SET tokens_valid_after = tokens_valid_after * 1000;
@@ -1,228 +1,204 @@
+use std::ops::Deref;
+
+use actix_web::{dev::Payload, web::Data, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
 use libsql::{params, Builder, Connection, Database};
-use std::{sync::Arc, time::Duration};
+use log::info;
+
+use crate::config::DatabaseConfig;
+use crate::error::AppError;
+
+use migrate::{latest_version, MigrateError, MIGRATIONS};
+
+pub mod migrate;
 
 pub struct Db {
+    /// Runs migrations, and is the only connection an in-memory database has.
     conn: Connection,
     database: Database,
+    /// Only replicas have a primary to sync with.
+    replica: bool,
+    memory: bool,
 }
 
 impl Db {
-    pub async fn init(url: String, auth_token: String) -> Result<Self, libsql::Error> {
-        let db = Builder::new_remote_replica("local.db", url, auth_token)
-            .sync_interval(Duration::from_secs(30))
-            .build()
-            .await?;
+    pub async fn init(config: &DatabaseConfig) -> Result<Self, libsql::Error> {
+        let db = match config {
+            DatabaseConfig::Replica {
+                url,
+                token,
+                path,
+                sync_interval,
+            } => {
+                Builder::new_remote_replica(path, url.clone(), token.expose().to_string())
+                    .sync_interval(*sync_interval)
+                    .build()
+                    .await?
+            }
+            DatabaseConfig::Local { path } => Builder::new_local(path).build().await?,
+            DatabaseConfig::Memory => Builder::new_local(":memory:").build().await?,
+        };
 
         let conn: Connection = db.connect()?;
 
-        Ok(Self { conn, database: db })
+        Ok(Self {
+            conn,
+            database: db,
+            replica: matches!(config, DatabaseConfig::Replica { .. }),
+            memory: matches!(config, DatabaseConfig::Memory),
+        })
     }
 
-    pub async fn create_db(&self) -> Result<(), libsql::Error> {
-        let create_users_table = r#"
-                CREATE TABLE IF NOT EXISTS users (
-                    id TEXT PRIMARY KEY,
-                    roll TEXT NOT NULL UNIQUE,
-                    username TEXT NOT NULL UNIQUE,
-                    password TEXT NOT NULL,
-                    first_name TEXT NOT NULL,
-                    last_name TEXT NOT NULL,
-                    posts INTEGER DEFAULT 0,
-                    followers INTEGER DEFAULT 0,
-                    following INTEGER DEFAULT 0,
-                    email TEXT NOT NULL UNIQUE,
-                    dob DATE NOT NULL,
-                    is_active BOOLEAN DEFAULT FALSE,
-                    is_superuser BOOLEAN DEFAULT FALSE,
-                    profile_url TEXT,
-                    bio TEXT,
-                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-                );
-                "#;
-
-        let create_followers_table = r#"
-                CREATE TABLE IF NOT EXISTS followers (
-                    follower_id TEXT NOT NULL,
-                    followed_id TEXT NOT NULL,
-                    PRIMARY KEY (follower_id, followed_id),
-                    FOREIGN KEY (follower_id) REFERENCES users (id) ON DELETE CASCADE,
-                    FOREIGN KEY (followed_id) REFERENCES users (id) ON DELETE CASCADE
-                );
-            "#;
-
-        let create_posts_table = r#"
-                CREATE TABLE IF NOT EXISTS posts (
-                    id TEXT PRIMARY KEY,
-                    user TEXT NOT NULL,
-                    text TEXT,
-                    public BOOLEAN DEFAULT TRUE,
-                    likes INTEGER DEFAULT 0,
-                    comments INTEGER DEFAULT 0,
-                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                    FOREIGN KEY (user) REFERENCES users (id) ON DELETE CASCADE
-                )
-            "#;
-
-        let create_post_image_table = r#"
-                CREATE TABLE IF NOT EXISTS post_images (
-                    id TEXT PRIMARY KEY,
-                    post TEXT NOT NULL,
-                    image_url TEXT NOT NULL,
-                    FOREIGN KEY (post) REFERENCES posts (id) ON DELETE CASCADE
-                )
-            "#;
-
-        let create_post_likes_table = r#"
-                CREATE TABLE IF NOT EXISTS post_likes (
-                    id TEXT PRIMARY KEY,
-                    post TEXT NOT NULL,
-                    user TEXT NOT NULL,
-                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                    FOREIGN KEY (post) REFERENCES posts (id) ON DELETE CASCADE,
-                    FOREIGN KEY (user) REFERENCES users (id) ON DELETE CASCADE
-                )
-            "#;
-
-        let create_post_comments_table = r#"
-                CREATE TABLE IF NOT EXISTS post_comments (
-                    id TEXT PRIMARY KEY,
-                    post TEXT NOT NULL,
-                    user TEXT NOT NULL,
-                    text TEXT NOT NULL,
-                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                    FOREIGN KEY (post) REFERENCES posts (id) ON DELETE CASCADE,
-                    FOREIGN KEY (user) REFERENCES users (id) ON DELETE CASCADE
-                )
-            "#;
-
-        let create_otp_table = r#"
-                CREATE TABLE IF NOT EXISTS otps (
-                    email TEXT PRIMARY KEY,
-                    otp TEXT NOT NULL,
-                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-                );
-                "#;
-
-        let create_tokens_table = r#"
-                CREATE TABLE IF NOT EXISTS tokens (
-                    token TEXT PRIMARY KEY
-                );
-                "#;
-
-        let create_user_id_index = r#"
-                CREATE INDEX IF NOT EXISTS idx_id ON users (id);
-                "#;
-
-        let create_follower_id_index = r#"
-                CREATE INDEX IF NOT EXISTS idx_follower_id ON followers (follower_id);
-                "#;
-
-        let create_followed_id_index = r#"
-                CREATE INDEX IF NOT EXISTS idx_followed_id ON followers (followed_id);
-                "#;
+    pub fn get_conn(&self) -> &Connection {
+        &self.conn
+    }
 
-        let create_otp_email_index = r#"
-                CREATE INDEX IF NOT EXISTS idx_email ON otps (email);
-                "#;
+    /// A connection of its own, so that its transactions can't take in or
+    /// roll back another caller's writes.
+    ///
+    /// An in-memory database only exists on the connection that opened it,
+    /// so in `memory` mode every caller shares that one instead.
+    pub async fn connect(&self) -> Result<Connection, libsql::Error> {
+        if self.memory {
+            return Ok(self.conn.clone());
+        }
+
+        let conn = self.database.connect()?;
+        // Wait for other connections' writes instead of failing with
+        // SQLITE_BUSY. Replicas send their writes to the primary.
+        if !self.replica {
+            conn.query("PRAGMA busy_timeout = 5000", params!()).await?;
+        }
+        Ok(conn)
+    }
 
-        self.conn.execute(create_users_table, params!()).await?;
-        self.conn.execute(create_followers_table, params!()).await?;
-        self.conn.execute(create_posts_table, params!()).await?;
-        self.conn
-            .execute(create_post_image_table, params!())
-            .await?;
+    async fn schema_version(&self) -> Result<i64, libsql::Error> {
         self.conn
-            .execute(create_post_likes_table, params!())
-            .await?;
-        self.conn
-            .execute(create_post_comments_table, params!())
-            .await?;
-        self.conn
-            .execute(create_follower_id_index, params!())
+            .execute(
+                r#"
+                CREATE TABLE IF NOT EXISTS schema_migrations (
+                    version INTEGER PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                );
+                "#,
+                params!(),
+            )
             .await?;
-        self.conn
-            .execute(create_followed_id_index, params!())
+
+        let mut rows = self
+            .conn
+            .query("SELECT MAX(version) FROM schema_migrations", params!())
             .await?;
-        self.conn.execute(create_otp_table, params!()).await?;
-        self.conn.execute(create_otp_email_index, params!()).await?;
-        self.conn.execute(create_user_id_index, params!()).await?;
-        self.conn.execute(create_tokens_table, params!()).await?;
 
-        Ok(())
+        Ok(match rows.next().await? {
+            Some(row) => row.get::<Option<i64>>(0)?.unwrap_or(0),
+            None => 0,
+        })
     }
 
-    pub fn get_db(&self) -> &Database {
-        &self.database
-    }
+    /// Applies every pending migration, each in its own transaction.
+    /// Fails without touching the schema if the database is ahead of this binary.
+    pub async fn migrate(&self) -> Result<(), MigrateError> {
+        // Pull the latest state from the primary before deciding what is pending.
+        if self.replica {
+            self.database.sync().await?;
+        }
+
+        let current = self.schema_version().await?;
+        let latest = latest_version();
+
+        if current > latest {
+            return Err(MigrateError::DatabaseAhead {
+                database: current,
+                binary: latest,
+            });
+        }
+
+        for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+            info!(
+                "Applying migration {:04}_{}",
+                migration.version, migration.name
+            );
+
+            let tran = self.conn.transaction().await?;
+            tran.execute_batch(migration.up).await?;
+            tran.execute(
+                "INSERT INTO schema_migrations (version, name) VALUES (?1, ?2)",
+                params![migration.version, migration.name],
+            )
+            .await?;
+            tran.commit().await?;
+        }
 
-    pub fn get_conn(&self) -> &Connection {
-        &self.conn
+        Ok(())
     }
 
-    pub async fn drop_db(&self) -> Result<(), libsql::Error> {
-        let drop_users_table = r#"
-            DROP TABLE IF EXISTS users;
-            "#;
-
-        let drop_followers_table = r#"
-            DROP TABLE IF EXISTS followers;
-            "#;
-
-        let drop_posts_table = r#"
-            DROP TABLE IF EXISTS posts;
-            "#;
-
-        let drop_post_images_table = r#"
-            DROP TABLE IF EXISTS post_images;
-            "#;
-
-        let drop_post_likes_table = r#"
-            DROP TABLE IF EXISTS post_likes;
-            "#;
-
-        let drop_post_comments_table = r#"
-            DROP TABLE IF EXISTS post_comments;
-            "#;
-
-        let drop_otp_table = r#"
-            DROP TABLE IF EXISTS otps;
-            "#;
+    /// Reverts applied migrations, newest first, until the schema is at `target`.
+    pub async fn rollback(&self, target: i64) -> Result<(), MigrateError> {
+        let current = self.schema_version().await?;
+
+        if current > latest_version() {
+            return Err(MigrateError::DatabaseAhead {
+                database: current,
+                binary: latest_version(),
+            });
+        }
+
+        if target < 0 || (target > 0 && !MIGRATIONS.iter().any(|m| m.version == target)) {
+            return Err(MigrateError::UnknownVersion(target));
+        }
+
+        for migration in MIGRATIONS
+            .iter()
+            .rev()
+            .filter(|m| m.version <= current && m.version > target)
+        {
+            info!(
+                "Reverting migration {:04}_{}",
+                migration.version, migration.name
+            );
+
+            let tran = self.conn.transaction().await?;
+            tran.execute_batch(migration.down).await?;
+            tran.execute(
+                "DELETE FROM schema_migrations WHERE version = ?1",
+                params![migration.version],
+            )
+            .await?;
+            tran.commit().await?;
+        }
 
-        let drop_tokens_table = r#"
-            DROP TABLE IF EXISTS tokens;
-            "#;
+        Ok(())
+    }
+}
 
-        let drop_users_id_index = r#"
-            DROP INDEX IF EXISTS idx_id;
-            "#;
+/// Extracts a connection for one request from the app's `Db`.
+pub struct Conn(Connection);
 
-        let drop_email_id_index = r#"
-            DROP INDEX IF EXISTS idx_email;
-            "#;
+impl Conn {
+    pub fn into_inner(self) -> Connection {
+        self.0
+    }
+}
 
-        let drop_follower_id_index = r#"
-            DROP INDEX IF EXISTS idx_follower_id;
-            "#;
+impl Deref for Conn {
+    type Target = Connection;
 
-        let drop_followed_id_index = r#"
-            DROP INDEX IF EXISTS idx_followed_id;
-            "#;
+    fn deref(&self) -> &Connection {
+        &self.0
+    }
+}
 
-        self.conn.execute(drop_users_table, params!()).await?;
-        self.conn.execute(drop_followers_table, params!()).await?;
-        self.conn.execute(drop_otp_table, params!()).await?;
-        self.conn.execute(drop_posts_table, params!()).await?;
-        self.conn
-            .execute(drop_post_comments_table, params!())
-            .await?;
-        self.conn.execute(drop_post_images_table, params!()).await?;
-        self.conn.execute(drop_post_likes_table, params!()).await?;
-        self.conn.execute(drop_tokens_table, params!()).await?;
-        self.conn.execute(drop_email_id_index, params!()).await?;
-        self.conn.execute(drop_follower_id_index, params!()).await?;
-        self.conn.execute(drop_followed_id_index, params!()).await?;
-        self.conn.execute(drop_users_id_index, params!()).await?;
+impl FromRequest for Conn {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, AppError>>;
 
-        Ok(())
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let db = req.app_data::<Data<Db>>().cloned();
+        Box::pin(async move {
+            let db = db.ok_or_else(|| AppError::internal("no database configured"))?;
+            Ok(Conn(db.connect().await?))
+        })
     }
 }
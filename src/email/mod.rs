@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use lettre::{
+    message::{Mailbox, MultiPart},
+    Message,
+};
+use libsql::Connection;
+use tokio::sync::Notify;
+
+use crate::config::Branding;
+use crate::error::AppError;
+use crate::models::{outbox::QueueEmail, user::Recipient};
+use templates::{Locale, Mail, Rendered, SecurityAlert, Template, Templates};
+
+pub mod templates;
+pub mod transport;
+
+/// Composes the app's emails and puts them in the outbox, from which
+/// `jobs::deliver_email` sends them.
+#[derive(Clone)]
+pub struct Email {
+    from: Mailbox,
+    templates: Arc<Templates>,
+    queued: Arc<Notify>,
+}
+
+impl Email {
+    pub fn new(from: Mailbox, branding: Branding) -> Self {
+        Self {
+            from,
+            templates: Arc::new(Templates::new(branding)),
+            queued: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Woken whenever a message is queued.
+    pub fn queued(&self) -> Arc<Notify> {
+        self.queued.clone()
+    }
+
+    pub async fn send_otp(
+        &self,
+        to: &Recipient,
+        otp: &str,
+        conn: &Connection,
+    ) -> Result<(), AppError> {
+        self.queue(to, Mail::otp(otp), conn).await
+    }
+
+    pub async fn send_password_reset(
+        &self,
+        to: &Recipient,
+        otp: &str,
+        conn: &Connection,
+    ) -> Result<(), AppError> {
+        self.queue(to, Mail::password_reset(otp), conn).await
+    }
+
+    pub async fn send_welcome(&self, to: &Recipient, conn: &Connection) -> Result<(), AppError> {
+        self.queue(to, Mail::Welcome {}, conn).await
+    }
+
+    pub async fn send_security_alert(
+        &self,
+        to: &Recipient,
+        alert: SecurityAlert,
+        conn: &Connection,
+    ) -> Result<(), AppError> {
+        self.queue(to, Mail::SecurityAlert(alert), conn).await
+    }
+
+    /// Renders `template` with its sample data, for previews.
+    pub fn preview(&self, template: Template, locale: Locale) -> Result<Rendered, AppError> {
+        self.templates
+            .render(&template.sample(), locale, Some("Asha"))
+            .map_err(AppError::internal)
+    }
+
+    async fn queue(&self, to: &Recipient, mail: Mail, conn: &Connection) -> Result<(), AppError> {
+        let rendered = self
+            .templates
+            .render(&mail, to.locale, to.first_name.as_deref())
+            .map_err(AppError::internal)?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.email.parse().map_err(AppError::internal)?)
+            .subject(&rendered.subject)
+            .multipart(MultiPart::alternative_plain_html(
+                rendered.text,
+                rendered.html,
+            ))
+            .map_err(AppError::internal)?;
+
+        QueueEmail {
+            subject: &rendered.subject,
+            message: &message,
+        }
+        .insert_into_db(conn)
+        .await?;
+        self.queued.notify_one();
+
+        Ok(())
+    }
+
+    pub fn generate_otp() -> String {
+        use rand::distributions::Alphanumeric;
+        use rand::{thread_rng, Rng};
+
+        thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(6)
+            .map(char::from)
+            .collect()
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use minijinja::{context, value::Kwargs, Environment, Error, ErrorKind, State, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Branding;
+use crate::models::otp::OTP_TTL_MINUTES;
+
+// ======================================== LOCALES ==========================================
+
+/// A language the emails are written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Hi,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::Hi];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Hi => "hi",
+        }
+    }
+
+    /// Parses a language tag such as `hi` or `hi-IN`, ignoring the region.
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let language = tag.split(['-', '_']).next()?.trim();
+        Locale::ALL
+            .into_iter()
+            .find(|l| l.as_str().eq_ignore_ascii_case(language))
+    }
+
+    /// The supported locale the client prefers most according to an
+    /// `Accept-Language` header, or the default one.
+    pub fn negotiate(accept_language: &str) -> Locale {
+        let mut preferences: Vec<(&str, f32)> = accept_language
+            .split(',')
+            .filter_map(|part| {
+                let mut pieces = part.split(';');
+                let tag = pieces.next()?.trim();
+                let quality = pieces
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse().ok())?;
+                Some((tag, quality))
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+        preferences.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        preferences
+            .into_iter()
+            .find_map(|(tag, _)| Locale::from_tag(tag))
+            .unwrap_or_default()
+    }
+
+    fn catalog(&self) -> &'static str {
+        match self {
+            Locale::En => include_str!("locales/en.toml"),
+            Locale::Hi => include_str!("locales/hi.toml"),
+        }
+    }
+}
+
+/// Every translated string, keyed by locale and then by dotted key such as
+/// `otp.subject`.
+struct Catalog(HashMap<Locale, HashMap<String, String>>);
+
+impl Catalog {
+    fn load() -> Self {
+        let mut strings = HashMap::new();
+        for locale in Locale::ALL {
+            let table: toml::Table = locale
+                .catalog()
+                .parse()
+                .unwrap_or_else(|e| panic!("locales/{}.toml is invalid: {}", locale.as_str(), e));
+            let mut flat = HashMap::new();
+            flatten("", &table, &mut flat);
+            strings.insert(locale, flat);
+        }
+        Catalog(strings)
+    }
+
+    /// Falls back to the default locale for strings that aren't translated.
+    fn get(&self, locale: Locale, key: &str) -> Option<&str> {
+        self.0[&locale]
+            .get(key)
+            .or_else(|| self.0[&Locale::default()].get(key))
+            .map(String::as_str)
+    }
+}
+
+fn flatten(prefix: &str, table: &toml::Table, out: &mut HashMap<String, String>) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            toml::Value::Table(table) => flatten(&key, table, out),
+            toml::Value::String(s) => {
+                out.insert(key, s.clone());
+            }
+            other => {
+                out.insert(key, other.to_string());
+            }
+        }
+    }
+}
+
+// ======================================== MAILS ==========================================
+
+/// The kinds of email the app sends. Each has a `<name>.txt` and a
+/// `<name>.html` template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Template {
+    Otp,
+    PasswordReset,
+    Welcome,
+    SecurityAlert,
+    Digest,
+}
+
+impl Template {
+    pub const ALL: [Template; 5] = [
+        Template::Otp,
+        Template::PasswordReset,
+        Template::Welcome,
+        Template::SecurityAlert,
+        Template::Digest,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Template::Otp => "otp",
+            Template::PasswordReset => "password_reset",
+            Template::Welcome => "welcome",
+            Template::SecurityAlert => "security_alert",
+            Template::Digest => "digest",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Template> {
+        Template::ALL.into_iter().find(|t| t.name() == name)
+    }
+
+    /// Made-up data to preview the template with.
+    pub fn sample(&self) -> Mail {
+        match self {
+            Template::Otp => Mail::otp("A1b2C3"),
+            Template::PasswordReset => Mail::password_reset("A1b2C3"),
+            Template::Welcome => Mail::Welcome {},
+            Template::SecurityAlert => Mail::SecurityAlert(SecurityAlert {
+                kind: AlertKind::PasswordChanged,
+                time: "2024-11-20 09:30 UTC".to_string(),
+                device: Some("Mozilla/5.0 (X11; Linux x86_64) Firefox/132.0".to_string()),
+                ip: Some("203.0.113.7".to_string()),
+            }),
+            Template::Digest => Mail::Digest(Digest {
+                days: 7,
+                new_followers: 3,
+                likes: 12,
+                comments: 4,
+                posts: vec![
+                    DigestPost {
+                        author: "priya".to_string(),
+                        text: "Anyone up for the robotics club meetup on Friday?".to_string(),
+                    },
+                    DigestPost {
+                        author: "rahul_k".to_string(),
+                        text: "Library is open till midnight during exams.".to_string(),
+                    },
+                ],
+            }),
+        }
+    }
+}
+
+/// An email and the data its template needs.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Mail {
+    Otp {
+        code: String,
+        minutes: i64,
+    },
+    PasswordReset {
+        code: String,
+        minutes: i64,
+    },
+    Welcome {},
+    SecurityAlert(SecurityAlert),
+    /// Only previewed for now. Nothing sends digests yet.
+    Digest(Digest),
+}
+
+impl Mail {
+    pub fn otp(code: &str) -> Self {
+        Mail::Otp {
+            code: code.to_string(),
+            minutes: OTP_TTL_MINUTES,
+        }
+    }
+
+    pub fn password_reset(code: &str) -> Self {
+        Mail::PasswordReset {
+            code: code.to_string(),
+            minutes: OTP_TTL_MINUTES,
+        }
+    }
+
+    pub fn template(&self) -> Template {
+        match self {
+            Mail::Otp { .. } => Template::Otp,
+            Mail::PasswordReset { .. } => Template::PasswordReset,
+            Mail::Welcome {} => Template::Welcome,
+            Mail::SecurityAlert(_) => Template::SecurityAlert,
+            Mail::Digest(_) => Template::Digest,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    PasswordChanged,
+    /// A refresh token was used twice and its session revoked.
+    TokenReused,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SecurityAlert {
+    pub kind: AlertKind,
+    pub time: String,
+    /// The user agent of the request that triggered the alert.
+    pub device: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Digest {
+    /// How many days the digest covers.
+    pub days: i64,
+    pub new_followers: i64,
+    pub likes: i64,
+    pub comments: i64,
+    pub posts: Vec<DigestPost>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DigestPost {
+    pub author: String,
+    pub text: String,
+}
+
+// ======================================== RENDERING ==========================================
+
+/// A rendered email, ready to be sent as `multipart/alternative`.
+#[derive(Debug, Serialize)]
+pub struct Rendered {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+/// The compiled templates. Templates and translations are built into the
+/// binary.
+///
+/// Templates get `brand`, `locale` and `name` (the recipient's first name,
+/// if known) along with the fields of their `Mail`. Text comes from the
+/// locale's catalog through `t("key", placeholder=value)`. The subject is
+/// the `subject` block of the text template.
+pub struct Templates {
+    env: Environment<'static>,
+    branding: Branding,
+}
+
+impl Templates {
+    pub fn new(branding: Branding) -> Self {
+        let mut env = Environment::new();
+        for (name, source) in [
+            ("layout.txt", include_str!("templates/layout.txt")),
+            ("layout.html", include_str!("templates/layout.html")),
+            ("otp.txt", include_str!("templates/otp.txt")),
+            ("otp.html", include_str!("templates/otp.html")),
+            (
+                "password_reset.txt",
+                include_str!("templates/password_reset.txt"),
+            ),
+            (
+                "password_reset.html",
+                include_str!("templates/password_reset.html"),
+            ),
+            ("welcome.txt", include_str!("templates/welcome.txt")),
+            ("welcome.html", include_str!("templates/welcome.html")),
+            (
+                "security_alert.txt",
+                include_str!("templates/security_alert.txt"),
+            ),
+            (
+                "security_alert.html",
+                include_str!("templates/security_alert.html"),
+            ),
+            ("digest.txt", include_str!("templates/digest.txt")),
+            ("digest.html", include_str!("templates/digest.html")),
+        ] {
+            env.add_template(name, source)
+                .unwrap_or_else(|e| panic!("email template {} is invalid: {}", name, e));
+        }
+
+        let catalog = Arc::new(Catalog::load());
+        let brand = branding.name.clone();
+        env.add_function(
+            "t",
+            move |state: &State, key: &str, args: Kwargs| -> Result<String, Error> {
+                let locale = state
+                    .lookup("locale")
+                    .and_then(|l| l.as_str().and_then(Locale::from_tag))
+                    .unwrap_or_default();
+                let mut text = catalog
+                    .get(locale, key)
+                    .ok_or_else(|| {
+                        Error::new(ErrorKind::UndefinedError, format!("no string {}", key))
+                    })?
+                    .replace("{brand}", &brand);
+                for name in args.args() {
+                    let value: Value = args.get(name)?;
+                    text = text.replace(&format!("{{{}}}", name), &value.to_string());
+                }
+                Ok(text)
+            },
+        );
+
+        Self { env, branding }
+    }
+
+    pub fn render(
+        &self,
+        mail: &Mail,
+        locale: Locale,
+        name: Option<&str>,
+    ) -> Result<Rendered, Error> {
+        let ctx = context! {
+            brand => &self.branding,
+            locale => locale.as_str(),
+            name => name,
+            ..Value::from_serialize(mail)
+        };
+        let template = mail.template().name();
+
+        let mut text = self
+            .env
+            .get_template(&format!("{}.txt", template))?
+            .render_captured(&ctx)?;
+        let subject = text.with_state_mut(|state| state.render_block("subject"))?;
+
+        Ok(Rendered {
+            subject: subject.trim().to_string(),
+            text: text.into_output(),
+            html: self
+                .env
+                .get_template(&format!("{}.html", template))?
+                .render(&ctx)?,
+        })
+    }
+}
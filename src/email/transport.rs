@@ -0,0 +1,166 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use lettre::{
+    address::Envelope, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Tokio1Executor,
+};
+use log::info;
+use uuid::Uuid;
+
+use crate::config::{MailTransport, Secret, SmtpTls};
+
+/// Hands a finished message to whatever delivers it. `message` is the
+/// complete RFC 5322 message as stored in the outbox.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Builds the transport selected by `MAIL_TRANSPORT`.
+pub fn init(config: &MailTransport) -> Result<Arc<dyn Transport>, Box<dyn std::error::Error>> {
+    Ok(match config {
+        MailTransport::Smtp {
+            host,
+            port,
+            tls,
+            credentials,
+        } => Arc::new(Smtp::init(host, *port, *tls, credentials.as_ref())?),
+        MailTransport::File { dir } => Arc::new(FileDrop::init(dir.clone())?),
+        MailTransport::Memory => Arc::new(Memory::default()),
+    })
+}
+
+// ===== SMTP =====
+
+pub struct Smtp(AsyncSmtpTransport<Tokio1Executor>);
+
+impl Smtp {
+    pub fn init(
+        host: &str,
+        port: Option<u16>,
+        tls: SmtpTls,
+        credentials: Option<&(String, Secret)>,
+    ) -> Result<Self, lettre::transport::smtp::Error> {
+        let mut builder = match tls {
+            SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        };
+        if let Some(port) = port {
+            builder = builder.port(port);
+        }
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                password.expose().to_string(),
+            ));
+        }
+
+        Ok(Self(builder.build()))
+    }
+}
+
+#[async_trait]
+impl Transport for Smtp {
+    async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.0.send_raw(envelope, message).await?;
+        Ok(())
+    }
+}
+
+// ===== FILE DROP =====
+
+/// Writes every message to its own `.eml` file, for reading mail during
+/// development without a mail server.
+pub struct FileDrop {
+    dir: PathBuf,
+}
+
+impl FileDrop {
+    pub fn init(dir: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+}
+
+#[async_trait]
+impl Transport for FileDrop {
+    async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Timestamped so a directory listing is in the order mail was sent.
+        let name = format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            Uuid::new_v4()
+        );
+        let path = self.dir.join(name);
+        tokio::fs::write(&path, message).await?;
+        info!(
+            "Wrote email to {} at {}",
+            recipients(envelope),
+            path.display()
+        );
+        Ok(())
+    }
+}
+
+// ===== MEMORY =====
+
+#[derive(Debug, Clone)]
+pub struct SentEmail {
+    pub to: Vec<String>,
+    pub message: String,
+}
+
+/// Keeps every message instead of sending it.
+#[derive(Default)]
+pub struct Memory {
+    sent: Mutex<Vec<SentEmail>>,
+}
+
+impl Memory {
+    /// Everything sent so far, oldest first.
+    pub fn sent(&self) -> Vec<SentEmail> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Transport for Memory {
+    async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.sent.lock().unwrap().push(SentEmail {
+            to: envelope.to().iter().map(|a| a.to_string()).collect(),
+            message: String::from_utf8_lossy(message).into_owned(),
+        });
+        Ok(())
+    }
+}
+
+fn recipients(envelope: &Envelope) -> String {
+    envelope
+        .to()
+        .iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
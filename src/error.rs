@@ -0,0 +1,153 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use log::error;
+use serde_json::{json, Value};
+use validator::ValidationErrors;
+
+use crate::models::cursor::InvalidCursor;
+
+/// Every error a handler can return. Each variant maps to one HTTP status and
+/// is rendered as `{"error": {"code": ..., "message": ...}}`, where `code` is
+/// a stable machine-readable string clients can branch on.
+///
+/// Variants that take a `&'static str` first carry that code; the others use
+/// the code returned by `AppError::code`.
+#[derive(Debug)]
+pub enum AppError {
+    Validation(ValidationErrors),
+    BadRequest(&'static str, String),
+    Unauthorized(&'static str, String),
+    Forbidden(&'static str, String),
+    NotFound(String),
+    Conflict(&'static str, String),
+    TooManyRequests(&'static str, String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    Database(libsql::Error),
+    Internal(String),
+}
+
+impl AppError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        AppError::NotFound(message.into())
+    }
+
+    /// Wraps an unexpected failure. The detail is logged but never sent to clients.
+    pub fn internal(e: impl fmt::Display) -> Self {
+        AppError::Internal(e.to_string())
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "validation_failed",
+            AppError::BadRequest(code, _)
+            | AppError::Unauthorized(code, _)
+            | AppError::Forbidden(code, _)
+            | AppError::Conflict(code, _)
+            | AppError::TooManyRequests(code, _) => code,
+            AppError::NotFound(_) => "not_found",
+            AppError::PayloadTooLarge(_) => "payload_too_large",
+            AppError::UnsupportedMediaType(_) => "unsupported_media_type",
+            AppError::Database(_) => "database_error",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            AppError::Validation(_) => "Validation failed".to_string(),
+            AppError::BadRequest(_, m)
+            | AppError::Unauthorized(_, m)
+            | AppError::Forbidden(_, m)
+            | AppError::Conflict(_, m)
+            | AppError::TooManyRequests(_, m)
+            | AppError::NotFound(m)
+            | AppError::PayloadTooLarge(m)
+            | AppError::UnsupportedMediaType(m) => m.clone(),
+            AppError::Database(_) | AppError::Internal(_) => "Something went wrong".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Validation(e) => write!(f, "validation failed: {}", e),
+            AppError::Database(e) => write!(f, "database error: {}", e),
+            AppError::Internal(e) => write!(f, "internal error: {}", e),
+            _ => write!(f, "{}: {}", self.code(), self.message()),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) | AppError::BadRequest(..) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(..) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(..) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(..) => StatusCode::CONFLICT,
+            AppError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.status_code().is_server_error() {
+            error!("{}", self);
+        }
+
+        let mut body = json!({
+            "code": self.code(),
+            "message": self.message(),
+        });
+
+        if let AppError::Validation(e) = self {
+            body["details"] = serde_json::to_value(e).unwrap_or(Value::Null);
+        }
+
+        HttpResponse::build(self.status_code()).json(json!({ "error": body }))
+    }
+}
+
+impl From<libsql::Error> for AppError {
+    fn from(e: libsql::Error) -> Self {
+        AppError::Database(e)
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(e: ValidationErrors) -> Self {
+        AppError::Validation(e)
+    }
+}
+
+impl From<InvalidCursor> for AppError {
+    fn from(e: InvalidCursor) -> Self {
+        AppError::BadRequest("invalid_cursor", e.to_string())
+    }
+}
+
+impl From<bcrypt::BcryptError> for AppError {
+    fn from(e: bcrypt::BcryptError) -> Self {
+        AppError::internal(e)
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(e: serde_json::Error) -> Self {
+        AppError::internal(e)
+    }
+}
+
+impl From<actix_web::error::BlockingError> for AppError {
+    fn from(e: actix_web::error::BlockingError) -> Self {
+        AppError::internal(e)
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::web::Data;
+use log::{error, info, warn};
+use tokio::sync::Notify;
+
+use crate::auth::token::Claims;
+use crate::db::Db;
+use crate::email::transport::Transport;
+use crate::error::AppError;
+use crate::models::outbox::{DueEmail, OutboxEntry, SendFailure};
+use crate::models::user::User;
+
+/// How often unverified accounts are looked for.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How often expired entries are removed from the token blacklist.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How often old mail is removed from the outbox.
+const OUTBOX_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How often the outbox is checked for retries that have come due. New mail
+/// wakes the worker straight away.
+const OUTBOX_INTERVAL: Duration = Duration::from_secs(15);
+/// How many messages are claimed at a time.
+const OUTBOX_BATCH: i64 = 20;
+
+/// Periodically deletes accounts left unverified for more than `max_age_days`.
+pub fn purge_unverified_users(db: Data<Db>, max_age_days: i64) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let purged = match db.connect().await {
+                Ok(conn) => User::purge_unverified(max_age_days, &conn).await,
+                Err(e) => Err(e.into()),
+            };
+            match purged {
+                Ok(0) => {}
+                Ok(n) => info!("Purged {} unverified accounts", n),
+                Err(e) => error!("Error purging unverified accounts: {}", e),
+            }
+        }
+    });
+}
+
+/// Periodically deletes blacklist entries for tokens that have expired.
+pub fn prune_token_blacklist(db: Data<Db>) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let pruned = match db.connect().await {
+                Ok(conn) => Claims::prune_blacklist(&conn).await,
+                Err(e) => Err(e.into()),
+            };
+            match pruned {
+                Ok(0) => {}
+                Ok(n) => info!("Pruned {} expired blacklisted tokens", n),
+                Err(e) => error!("Error pruning token blacklist: {}", e),
+            }
+        }
+    });
+}
+
+/// Periodically deletes mail that was sent or given up on more than
+/// `retention_days` ago.
+pub fn prune_outbox(db: Data<Db>, retention_days: i64) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(OUTBOX_PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let pruned = match db.connect().await {
+                Ok(conn) => OutboxEntry::prune(retention_days, &conn).await,
+                Err(e) => Err(e.into()),
+            };
+            match pruned {
+                Ok(0) => {}
+                Ok(n) => info!("Pruned {} old emails from the outbox", n),
+                Err(e) => error!("Error pruning the outbox: {}", e),
+            }
+        }
+    });
+}
+
+/// Sends mail from the outbox as it is queued, retrying failed sends with
+/// backoff until they are given up on.
+pub fn deliver_email(db: Data<Db>, transport: Arc<dyn Transport>, queued: Arc<Notify>) {
+    actix_web::rt::spawn(async move {
+        loop {
+            if let Err(e) = deliver_due(&db, transport.as_ref()).await {
+                error!("Error delivering email: {}", e);
+            }
+            let _ = actix_web::rt::time::timeout(OUTBOX_INTERVAL, queued.notified()).await;
+        }
+    });
+}
+
+async fn deliver_due(db: &Db, transport: &dyn Transport) -> Result<(), AppError> {
+    let conn = &db.connect().await?;
+    loop {
+        let due = DueEmail::claim(conn, OUTBOX_BATCH).await?;
+        let claimed = due.len() as i64;
+
+        for email in due {
+            match transport.send(&email.envelope, &email.message).await {
+                Ok(()) => email.mark_sent(conn).await?,
+                Err(e) => match email.mark_failed(&e.to_string(), conn).await? {
+                    SendFailure::Retry(delay) => warn!(
+                        "Sending email {} failed, retrying in {}s: {}",
+                        email.id, delay, e
+                    ),
+                    SendFailure::GaveUp => {
+                        error!("Sending email {} failed, giving up: {}", email.id, e)
+                    }
+                },
+            }
+        }
+
+        if claimed < OUTBOX_BATCH {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use actix_multipart::{form::MultipartFormConfig, MultipartError};
+use actix_web::error::PayloadError;
+use actix_web::middleware::from_fn;
+use actix_web::{web, HttpResponse};
+use admin::{
+    change_role, create_institution, deactivate_user, delete_institution, list_email_templates,
+    list_institutions, list_outbox, list_users, preview_email, remove_comment, remove_post,
+    retry_email, update_institution,
+};
+use auth::cache::RevocationCache;
+use auth::token::JWT;
+use auth::{
+    forgot_password, jwks, list_sessions, login, logout, refresh_tokens, register_user,
+    resend_verification, reset_password, revoke_other_sessions, revoke_session, send_otp,
+    verify_otp,
+};
+use db::Db;
+use email::Email;
+use error::AppError;
+use posts::*;
+use profile::{
+    avatar::MAX_AVATAR_BYTES, follow, list_followers, list_following, search, unfollow, update,
+    upload_avatar,
+};
+use storage::{local::LocalStorage, Storage};
+
+pub mod admin;
+pub mod auth;
+pub mod config;
+pub mod db;
+pub mod email;
+pub mod error;
+pub mod jobs;
+pub mod middleware;
+pub mod models;
+pub mod posts;
+pub mod profile;
+pub mod storage;
+
+/// Everything the handlers share, built once at startup.
+#[derive(Clone)]
+pub struct AppState {
+    pub db: web::Data<Db>,
+    pub mailer: web::Data<Email>,
+    pub jwt: web::Data<JWT>,
+    pub revocations: web::Data<RevocationCache>,
+    pub storage: web::Data<dyn Storage>,
+    /// Set when uploads are stored on disk and served by this server.
+    pub local_storage: Option<web::Data<LocalStorage>>,
+}
+
+/// Mounts every route of the API on an `App`.
+pub fn configure(state: AppState) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        cfg.app_data(state.db)
+            .app_data(state.mailer)
+            .app_data(state.jwt)
+            .app_data(state.revocations)
+            .app_data(web::JsonConfig::default().error_handler(|err, _req| {
+                AppError::BadRequest("malformed_body", err.to_string()).into()
+            }))
+            .app_data(web::FormConfig::default().error_handler(|err, _req| {
+                AppError::BadRequest("malformed_body", err.to_string()).into()
+            }))
+            .app_data(web::QueryConfig::default().error_handler(|err, _req| {
+                AppError::BadRequest("malformed_query", err.to_string()).into()
+            }))
+            .app_data(state.storage)
+            .service(
+                web::scope("/auth")
+                    .service(register_user)
+                    .service(verify_otp)
+                    .service(send_otp)
+                    .service(resend_verification)
+                    .service(refresh_tokens)
+                    .service(login)
+                    .service(forgot_password)
+                    .service(reset_password),
+            )
+            .service(
+                web::scope("/profiles")
+                    .wrap(from_fn(middleware::jwt))
+                    .app_data(
+                        MultipartFormConfig::default()
+                            .memory_limit(MAX_AVATAR_BYTES)
+                            .total_limit(MAX_AVATAR_BYTES + 64 * 1024)
+                            .error_handler(|err, _req| match err {
+                                MultipartError::Payload(PayloadError::Overflow) => {
+                                    AppError::PayloadTooLarge(format!(
+                                        "Profile pictures can be at most {} bytes",
+                                        MAX_AVATAR_BYTES
+                                    ))
+                                    .into()
+                                }
+                                err => {
+                                    AppError::BadRequest("malformed_body", err.to_string()).into()
+                                }
+                            }),
+                    )
+                    .service(search)
+                    .service(update)
+                    .service(upload_avatar)
+                    .service(follow)
+                    .service(unfollow)
+                    .service(list_followers)
+                    .service(list_following)
+                    .service(logout)
+                    .service(list_sessions)
+                    .service(revoke_session)
+                    .service(revoke_other_sessions),
+            )
+            .service(
+                web::scope("/posts")
+                    .wrap(from_fn(middleware::jwt))
+                    .service(create)
+                    .service(list_other_posts)
+                    .service(list_friends_posts)
+                    .service(like)
+                    .service(unlike)
+                    .service(list_likes)
+                    .service(list_comments)
+                    .service(comment)
+                    .service(edit_comment)
+                    .service(delete_comment)
+                    .service(list_replies)
+                    .service(edit)
+                    .service(delete),
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(middleware::jwt))
+                    .service(
+                        web::scope("/institutions")
+                            .wrap(from_fn(middleware::admin))
+                            .service(list_institutions)
+                            .service(create_institution)
+                            .service(update_institution)
+                            .service(delete_institution),
+                    )
+                    .service(
+                        web::scope("/users")
+                            .wrap(from_fn(middleware::admin))
+                            .service(list_users)
+                            .service(deactivate_user)
+                            .service(change_role),
+                    )
+                    .service(
+                        web::scope("/outbox")
+                            .wrap(from_fn(middleware::admin))
+                            .service(list_outbox)
+                            .service(retry_email),
+                    )
+                    .service(
+                        web::scope("/emails")
+                            .wrap(from_fn(middleware::admin))
+                            .service(list_email_templates)
+                            .service(preview_email),
+                    )
+                    .service(
+                        web::scope("/posts")
+                            .wrap(from_fn(middleware::moderator))
+                            .service(remove_post),
+                    )
+                    .service(
+                        web::scope("/comments")
+                            .wrap(from_fn(middleware::moderator))
+                            .service(remove_comment),
+                    ),
+            );
+
+        if let Some(local) = state.local_storage {
+            storage::local::configure(local)(cfg);
+        }
+
+        cfg.service(web::scope("/.well-known").service(jwks))
+            .service(home)
+            .default_service(
+                web::route().to(|| async {
+                    Err::<HttpResponse, _>(AppError::not_found("Route not found"))
+                }),
+            );
+    }
+}
+
+#[actix_web::get("/")]
+async fn home() -> Result<HttpResponse, actix_web::Error> {
+    Ok(HttpResponse::Ok().body("Welcome to the home page"))
+}
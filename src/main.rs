@@ -1,26 +1,15 @@
-use actix_web::middleware::{from_fn, Logger};
-use actix_web::web::{Data, Json};
-use actix_web::{error, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder};
-use anyhow::Result;
-use auth::token::{Claims, JWT};
-use auth::{login, logout, refresh_tokens, register_user, send_otp, verify_otp};
-use libsql::{params, Connection};
-use posts::*;
-use profile::{search, update};
-use std::rc::Rc;
-use std::{env, fs::File, sync::Arc};
-
-mod auth;
-mod aws;
-mod db;
-mod email;
-mod middleware;
-mod models;
-mod posts;
-mod profile;
-
-use db::Db;
-use email::Email;
+use actix_web::middleware::Logger;
+use actix_web::{web, App, HttpServer};
+use log::info;
+use oncampus::auth::cache::RevocationCache;
+use oncampus::auth::token::JWT;
+use oncampus::config::{Config, StorageConfig};
+use oncampus::db::Db;
+use oncampus::email::{transport, Email};
+use oncampus::models::institution::Institution;
+use oncampus::storage::{local::LocalStorage, s3::S3, Storage};
+use oncampus::{jobs, AppState};
+use std::{env, sync::Arc};
 
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -42,77 +31,82 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     //     WriteLogger::new(LevelFilter::Debug, Config::default(), trace_log_file),
     // ])?;
 
-    let url = env::var("DB_DCRUST_URL")?;
-    let token = env::var("DB_DCRUST_TOKEN")?;
-    let email = env::var("EMAIL")?;
-    let email_pass = env::var("EMAIL_APP_PASSWORD")?;
-    // let access_key_id = env::var("AWS_ACCESS_KEY_ID")?;
-    // let secret_access_key = env::var("AWS_SECRET_ACCESS_KEY")?;
-    // let region = env::var("AWS_REGION")?;
-    // let bucket = env::var("AWS_BUCKET")?;
+    let config = Config::load()?;
+    info!("Loaded configuration: {:?}", config);
+
+    let db = Db::init(&config.database).await?;
 
-    let db = Db::init(url, token).await?;
-    // db.drop_db().await?;
-    db.create_db().await?;
-    let conn_data = web::Data::new(db.get_conn().clone());
+    // `oncampus rollback <version>` reverts the schema to <version> and exits.
+    if let Some("rollback") = env::args().nth(1).as_deref() {
+        let target = env::args()
+            .nth(2)
+            .ok_or("Usage: oncampus rollback <version>")?
+            .parse::<i64>()?;
+        db.rollback(target).await?;
+        return Ok(());
+    }
 
-    let mail_data = web::Data::new(Email::init(email, email_pass)?);
+    db.migrate().await?;
+    Institution::seed(&config.auth.domains, db.get_conn()).await?;
+    let db = web::Data::new(db);
 
-    let jwt = web::Data::new(JWT::init()?);
+    jobs::purge_unverified_users(db.clone(), config.auth.unverified_account_days);
+    jobs::prune_token_blacklist(db.clone());
+    jobs::prune_outbox(db.clone(), config.email.outbox_retention_days);
+
+    let mailer = Email::new(config.email.from.clone(), config.email.branding.clone());
+    jobs::deliver_email(
+        db.clone(),
+        transport::init(&config.email.transport)?,
+        mailer.queued(),
+    );
+
+    // Local storage also needs its upload and file serving routes mounted.
+    let mut local_storage = None;
+    let storage: web::Data<dyn Storage> = match &config.storage {
+        StorageConfig::S3 {
+            access_key_id,
+            secret_access_key,
+            region,
+            bucket,
+        } => {
+            let s3 = S3::init(
+                access_key_id.clone(),
+                secret_access_key.expose().to_string(),
+                region.clone(),
+                bucket.clone(),
+                "oncampus",
+            )
+            .await?;
+            web::Data::from(Arc::new(s3) as Arc<dyn Storage>)
+        }
+        StorageConfig::Local { dir, base_url } => {
+            let local = Arc::new(LocalStorage::init(dir, base_url.clone())?);
+            local_storage = Some(web::Data::from(local.clone()));
+            web::Data::from(local as Arc<dyn Storage>)
+        }
+    };
 
-    // let s3 = web::Data::new(
-    //     aws::S3::init(access_key_id, secret_access_key, region, bucket, "oncampus").await?,
-    // );
+    let state = AppState {
+        db,
+        mailer: web::Data::new(mailer),
+        jwt: web::Data::new(JWT::init(&config.jwt)?),
+        revocations: web::Data::new(RevocationCache::new(
+            config.jwt.cache_capacity,
+            config.jwt.cache_ttl,
+        )),
+        storage,
+        local_storage,
+    };
 
     HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
-            .app_data(conn_data.clone())
-            .app_data(mail_data.clone())
-            .app_data(jwt.clone())
-            .app_data(web::JsonConfig::default().error_handler(|err, _req| {
-                error::InternalError::from_response(
-                    err.to_string(),
-                    HttpResponse::BadRequest().body(err.to_string()),
-                )
-                .into()
-            }))
-            // .app_data(s3.clone())
-            .service(
-                web::scope("/auth")
-                    .service(register_user)
-                    .service(verify_otp)
-                    .service(send_otp)
-                    .service(refresh_tokens)
-                    .service(login),
-            )
-            .service(
-                web::scope("/profiles")
-                    .wrap(from_fn(middleware::jwt))
-                    .service(search)
-                    .service(update)
-                    .service(logout),
-            )
-            .service(
-                web::scope("/posts")
-                    .wrap(from_fn(middleware::jwt))
-                    .service(create)
-                    .service(list_other_posts)
-                    .service(like)
-                    .service(list_comments)
-                    .service(comment),
-            )
-            .service(home)
-            .default_service(web::route().to(|| async { actix_web::HttpResponse::NotFound() }))
+            .configure(oncampus::configure(state.clone()))
     })
-    .bind("127.0.0.1:8080")?
+    .bind(&config.server.bind)?
     .run()
     .await?;
 
     Ok(())
 }
-
-#[actix_web::get("/")]
-async fn home(req: HttpRequest) -> Result<HttpResponse, actix_web::Error> {
-    Ok(HttpResponse::Ok().body(format!("Welcome to the home page")))
-}
@@ -1,15 +1,18 @@
-use std::{rc::Rc, sync::Arc};
+use std::{sync::Arc, time::Duration};
 
+use crate::auth::cache::RevocationCache;
+use crate::auth::role::Role;
 use crate::auth::token::{Claims, JWT};
+use crate::db::Db;
+use crate::error::AppError;
 use actix_web::{
     body::{BoxBody, MessageBody},
-    dev::{Extensions, ServiceRequest, ServiceResponse},
-    error,
+    dev::{ServiceRequest, ServiceResponse},
     middleware::Next,
     web::Data,
     Error, HttpMessage,
 };
-use libsql::Connection;
+use chrono::Utc;
 
 pub async fn jwt<B>(req: ServiceRequest, next: Next<B>) -> Result<ServiceResponse<BoxBody>, Error>
 where
@@ -17,33 +20,43 @@ where
 {
     let jwt = req.app_data::<Data<JWT>>().unwrap();
 
-    let conn = req.app_data::<Data<Connection>>().unwrap();
-    let token = req
+    let db = req.app_data::<Data<Db>>().unwrap();
+    let cache = req.app_data::<Data<RevocationCache>>().unwrap();
+    let token = match req
         .headers()
         .get("Authorization")
-        .ok_or_else(|| error::ErrorUnauthorized("Token not found"))?
-        .to_str()
-        .unwrap()
-        .to_string()
-        .replace("Bearer ", "")
-        .replace(" ", "");
-
-    let mut claims: Claims;
-    if let Ok(r) = Claims::decode(&token, jwt) {
-        claims = r;
-    } else {
-        return Ok(req.error_response(error::ErrorUnauthorized("Invalid Token")));
-    }
+        .and_then(|h| h.to_str().ok())
+    {
+        Some(h) => h.replace("Bearer ", "").replace(" ", ""),
+        None => {
+            return Ok(req.error_response(AppError::Unauthorized(
+                "missing_token",
+                "Token not found".to_string(),
+            )))
+        }
+    };
+
+    let claims = match Claims::decode(&token, jwt) {
+        Ok(claims) => claims,
+        Err(e) => return Ok(req.error_response(e)),
+    };
 
     if claims.token != "access" {
-        return Ok(req.error_response(error::ErrorUnauthorized("Use Access token")));
+        return Ok(req.error_response(AppError::Unauthorized(
+            "wrong_token_type",
+            "Use Access token".to_string(),
+        )));
     }
 
-    if !Claims::is_valid(&token, conn, jwt)
-        .await
-        .map_err(|e| error::ErrorInternalServerError("Something went wrong"))?
-    {
-        return Ok(req.error_response(error::ErrorUnauthorized("Token is blacklisted")));
+    match is_valid(&claims, &token, cache, db).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Ok(req.error_response(AppError::Unauthorized(
+                "token_revoked",
+                "Token is blacklisted".to_string(),
+            )))
+        }
+        Err(e) => return Ok(req.error_response(e)),
     }
 
     req.extensions_mut().insert(Arc::new(claims));
@@ -52,3 +65,73 @@ where
     let res = next.call(req).await?;
     Ok(res.map_into_boxed_body())
 }
+
+/// Asks the revocation cache first and only goes to the database when the
+/// token isn't in it.
+async fn is_valid(
+    claims: &Claims,
+    token: &str,
+    cache: &RevocationCache,
+    db: &Db,
+) -> Result<bool, AppError> {
+    let key = claims.blacklist_key(token);
+    if cache.is_valid(key) {
+        return Ok(true);
+    }
+
+    let epoch = cache.epoch();
+    let valid = claims.is_valid(token, &db.connect().await?).await?;
+    if valid {
+        let left = (claims.exp as i64 - Utc::now().timestamp()).max(0) as u64;
+        cache.insert(
+            key,
+            &claims.sub,
+            claims.fam.as_deref(),
+            Duration::from_secs(left),
+            epoch,
+        );
+    }
+    Ok(valid)
+}
+
+/// Lets through moderators and admins. Must be wrapped inside `jwt`.
+pub async fn moderator<B>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<BoxBody>, Error>
+where
+    B: MessageBody + 'static,
+{
+    authorize(Role::Moderator, req, next).await
+}
+
+/// Lets through admins only. Must be wrapped inside `jwt`.
+pub async fn admin<B>(req: ServiceRequest, next: Next<B>) -> Result<ServiceResponse<BoxBody>, Error>
+where
+    B: MessageBody + 'static,
+{
+    authorize(Role::Admin, req, next).await
+}
+
+async fn authorize<B>(
+    role: Role,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<BoxBody>, Error>
+where
+    B: MessageBody + 'static,
+{
+    let allowed = match req.extensions().get::<Arc<Claims>>() {
+        Some(claims) => claims.role.require(role),
+        None => Err(AppError::Unauthorized(
+            "missing_token",
+            "Token not found".to_string(),
+        )),
+    };
+    if let Err(e) = allowed {
+        return Ok(req.error_response(e));
+    }
+
+    let res = next.call(req).await?;
+    Ok(res.map_into_boxed_body())
+}
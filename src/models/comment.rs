@@ -1,78 +1,349 @@
 use libsql::{params, Connection};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use validator::Validate;
 use validator_derive::Validate;
 
+use super::cursor::{Cursor, Page};
+use crate::error::AppError;
+
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CreateComment {
     pub post: String,
+    /// Comment being replied to. Replies can only be one level deep.
+    pub parent: Option<String>,
     #[validate(length(max = 500))]
     pub text: String,
 }
 
+/// Outcome of adding a comment.
+#[derive(Debug, PartialEq, Eq)]
+pub enum NewComment {
+    Created(String),
+    PostNotFound,
+    ParentNotFound,
+    /// The parent is itself a reply.
+    TooDeep,
+}
+
 impl CreateComment {
     pub async fn insert_into_db(
         &self,
-        user: &String,
+        user: &str,
         conn: &Connection,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<NewComment, AppError> {
         let text = self.text.clone();
         let post = self.post.clone();
+        let id = Uuid::new_v4().to_string();
 
         let tran = conn.transaction().await?;
-        tran.execute(
-            r#"
-            INSERT INTO post_comments (id, user, post, text)
-            VALUES (?1, ?2, ?3, ?4)
+
+        // Only inserts if the post exists and the parent, if any, is a top
+        // level comment on it. The counters only move if it was inserted.
+        let inserted = tran
+            .execute(
+                r#"
+            INSERT INTO post_comments (id, user, post, text, parent_id)
+            SELECT ?1, ?2, id, ?4, ?5 FROM posts
+            WHERE id = ?3
+            AND (?5 IS NULL OR EXISTS (
+                SELECT 1 FROM post_comments
+                WHERE id = ?5 AND post = ?3 AND parent_id IS NULL
+            ))
             "#,
-            params![Uuid::new_v4().to_string(), user.clone(), post.clone(), text],
-        )
-        .await?;
+                params![id.clone(), user, post.clone(), text, self.parent.clone()],
+            )
+            .await?;
 
         tran.execute(
             r#"
             UPDATE posts
             SET comments = comments + 1
-            WHERE id = ?1
+            WHERE id = ?1 AND changes() > 0
         "#,
             params![post],
         )
         .await?;
 
+        if let Some(parent) = &self.parent {
+            tran.execute(
+                r#"
+                UPDATE post_comments
+                SET replies = replies + 1
+                WHERE id = ?1 AND changes() > 0
+            "#,
+                params![parent.clone()],
+            )
+            .await?;
+        }
+
         tran.commit().await?;
 
-        Ok(())
+        if inserted > 0 {
+            return Ok(NewComment::Created(id));
+        }
+        self.rejection(conn).await
+    }
+
+    /// Why a comment was not inserted.
+    async fn rejection(&self, conn: &Connection) -> Result<NewComment, AppError> {
+        let Some(parent) = &self.parent else {
+            return Ok(NewComment::PostNotFound);
+        };
+
+        let mut rows = conn
+            .query(
+                "SELECT parent_id FROM post_comments WHERE id = ?1 AND post = ?2",
+                params![parent.clone(), self.post.clone()],
+            )
+            .await?;
+
+        Ok(match rows.next().await? {
+            None => NewComment::ParentNotFound,
+            Some(row) if row.get::<Option<String>>(0)?.is_some() => NewComment::TooDeep,
+            // The parent exists, so the post must have just been deleted.
+            Some(_) => NewComment::PostNotFound,
+        })
+    }
+}
+
+/// Outcome of an operation restricted to certain users of a comment.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommentAccess {
+    Allowed,
+    Forbidden,
+    NotFound,
+}
+
+struct CommentOwners {
+    author: String,
+    post: String,
+    post_owner: String,
+    parent: Option<String>,
+}
+
+impl CommentOwners {
+    async fn get(comment: &str, conn: &Connection) -> Result<Option<CommentOwners>, AppError> {
+        let mut rows = conn
+            .query(
+                r#"
+            SELECT post_comments.user, post_comments.post, posts.user, post_comments.parent_id
+            FROM post_comments
+            INNER JOIN posts ON posts.id = post_comments.post
+            WHERE post_comments.id = ?1
+            "#,
+                params![comment],
+            )
+            .await?;
+
+        Ok(match rows.next().await? {
+            Some(row) => Some(CommentOwners {
+                author: row.get(0)?,
+                post: row.get(1)?,
+                post_owner: row.get(2)?,
+                parent: row.get::<Option<String>>(3)?,
+            }),
+            None => None,
+        })
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct EditComment {
+    #[validate(length(max = 500))]
+    pub text: String,
+}
+
+impl EditComment {
+    /// Only the author may change what a comment says.
+    pub async fn update_into_db(
+        &self,
+        user: &str,
+        comment: &str,
+        conn: &Connection,
+    ) -> Result<CommentAccess, AppError> {
+        let updated = conn
+            .execute(
+                r#"
+            UPDATE post_comments
+            SET text = ?1, edited_at = CURRENT_TIMESTAMP
+            WHERE id = ?2 AND user = ?3
+            "#,
+                params![self.text.clone(), comment, user],
+            )
+            .await?;
+
+        if updated > 0 {
+            return Ok(CommentAccess::Allowed);
+        }
+        Ok(match CommentOwners::get(comment, conn).await? {
+            None => CommentAccess::NotFound,
+            Some(_) => CommentAccess::Forbidden,
+        })
+    }
+}
+
+pub struct DeleteComment;
+
+impl DeleteComment {
+    /// The comment author or the owner of the post may delete a comment.
+    /// Deleting a comment also deletes its replies.
+    pub async fn delete_from_db(
+        user: &str,
+        comment: &str,
+        conn: &Connection,
+    ) -> Result<CommentAccess, AppError> {
+        let owners = match CommentOwners::get(comment, conn).await? {
+            None => return Ok(CommentAccess::NotFound),
+            Some(owners) if owners.author != user && owners.post_owner != user => {
+                return Ok(CommentAccess::Forbidden)
+            }
+            Some(owners) => owners,
+        };
+
+        let tran = conn.transaction().await?;
+        remove_comment(comment, &owners, &tran).await?;
+
+        tran.commit().await?;
+        Ok(CommentAccess::Allowed)
+    }
+
+    /// Deletes `comment` and its replies whoever wrote them, for moderation.
+    /// Returns `false` if there is no such comment.
+    pub async fn remove_from_db(comment: &str, conn: &Connection) -> Result<bool, AppError> {
+        let Some(owners) = CommentOwners::get(comment, conn).await? else {
+            return Ok(false);
+        };
+
+        let tran = conn.transaction().await?;
+        remove_comment(comment, &owners, &tran).await?;
+
+        tran.commit().await?;
+        Ok(true)
+    }
+}
+
+/// Deletes `comment` and its replies and updates the counters they were part
+/// of. Nothing changes if the comment has already gone.
+async fn remove_comment(
+    comment: &str,
+    owners: &CommentOwners,
+    conn: &Connection,
+) -> Result<(), AppError> {
+    let deleted = conn
+        .execute(
+            r#"
+        DELETE FROM post_comments
+        WHERE id = ?1 OR parent_id = ?1
+        "#,
+            params![comment],
+        )
+        .await?;
+
+    conn.execute(
+        r#"
+        UPDATE posts
+        SET comments = MAX(comments - ?1, 0)
+        WHERE id = ?2 AND ?1 > 0
+        "#,
+        params![deleted as i64, owners.post.as_str()],
+    )
+    .await?;
+
+    if let Some(parent) = owners.parent.as_deref() {
+        conn.execute(
+            r#"
+            UPDATE post_comments
+            SET replies = MAX(replies - 1, 0)
+            WHERE id = ?1 AND changes() > 0
+            "#,
+            params![parent],
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RetrieveComment {
     pub id: String,
     pub user: String,
     pub username: String,
     pub text: String,
+    pub parent_id: Option<String>,
+    pub replies: u32,
     pub created_at: String,
+    pub edited_at: Option<String>,
 }
 
 impl RetrieveComment {
+    /// Top level comments on `post`.
     pub async fn retrieve_from_db(
-        post: &String,
+        post: &str,
         conn: &Connection,
-    ) -> Result<Vec<RetrieveComment>, Box<dyn std::error::Error>> {
-        let mut comments = vec![];
+        limit: i32,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<RetrieveComment>, AppError> {
+        Self::query(
+            r#"
+            SELECT post_comments.id, post_comments.user, users.username, post_comments.text,
+                post_comments.parent_id, post_comments.replies, post_comments.created_at, post_comments.edited_at
+            FROM users
+            INNER JOIN post_comments
+            ON users.id = post_comments.user
+            WHERE post_comments.post = ?1 AND post_comments.parent_id IS NULL
+            AND (?3 IS NULL OR (post_comments.created_at, post_comments.id) < (?3, ?4))
+            ORDER BY post_comments.created_at DESC, post_comments.id DESC
+            LIMIT ?2
+            "#,
+            post,
+            conn,
+            limit,
+            cursor,
+        )
+        .await
+    }
 
-        let mut rows = conn
-            .query(
-                r#"
-            SELECT post_comments.id, post_comments.user, users.username, post_comments.text, post_comments.created_at
+    /// Replies to the comment `parent`.
+    pub async fn replies_from_db(
+        parent: &str,
+        conn: &Connection,
+        limit: i32,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<RetrieveComment>, AppError> {
+        Self::query(
+            r#"
+            SELECT post_comments.id, post_comments.user, users.username, post_comments.text,
+                post_comments.parent_id, post_comments.replies, post_comments.created_at, post_comments.edited_at
             FROM users
             INNER JOIN post_comments
             ON users.id = post_comments.user
-            WHERE post_comments.post = ?1
-            ORDER BY post_comments.created_at DESC
+            WHERE post_comments.parent_id = ?1
+            AND (?3 IS NULL OR (post_comments.created_at, post_comments.id) < (?3, ?4))
+            ORDER BY post_comments.created_at DESC, post_comments.id DESC
+            LIMIT ?2
             "#,
-                params![post.clone()],
+            parent,
+            conn,
+            limit,
+            cursor,
+        )
+        .await
+    }
+
+    async fn query(
+        sql: &str,
+        key: &str,
+        conn: &Connection,
+        limit: i32,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<RetrieveComment>, AppError> {
+        let mut comments = vec![];
+
+        let mut rows = conn
+            .query(
+                sql,
+                params![key, limit + 1, Cursor::key(cursor), Cursor::id(cursor)],
             )
             .await?;
 
@@ -81,17 +352,25 @@ impl RetrieveComment {
             let user: String = row.get(1)?;
             let username: String = row.get(2)?;
             let text: String = row.get(3)?;
-            let created_at: String = row.get(4)?;
+            let parent_id: Option<String> = row.get::<Option<String>>(4)?;
+            let replies: u32 = row.get::<Option<u32>>(5)?.unwrap_or(0);
+            let created_at: String = row.get(6)?;
+            let edited_at: Option<String> = row.get::<Option<String>>(7)?;
 
             comments.push(RetrieveComment {
                 id,
                 user,
                 username,
                 text,
+                parent_id,
+                replies,
                 created_at,
+                edited_at,
             });
         }
 
-        Ok(comments)
+        Ok(Page::from_rows(comments, limit, |c| {
+            Cursor::new(&c.created_at, &c.id)
+        }))
     }
 }
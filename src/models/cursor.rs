@@ -0,0 +1,97 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PAGE_SIZE: i32 = 10;
+pub const MAX_PAGE_SIZE: i32 = 50;
+
+/// Opaque keyset position handed to clients as `next_cursor`.
+///
+/// `key` is the column the list is ordered by (`created_at` for posts and
+/// comments, `username` for profiles) and `id` breaks ties between rows that
+/// share the same key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cursor {
+    #[serde(rename = "k")]
+    pub key: String,
+    #[serde(rename = "i")]
+    pub id: String,
+}
+
+#[derive(Debug)]
+pub struct InvalidCursor;
+
+impl std::fmt::Display for InvalidCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid cursor")
+    }
+}
+
+impl std::error::Error for InvalidCursor {}
+
+impl Cursor {
+    pub fn new(key: impl Into<String>, id: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            id: id.into(),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, InvalidCursor> {
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| InvalidCursor)?;
+        let cursor: Cursor = serde_json::from_slice(&bytes).map_err(|_| InvalidCursor)?;
+
+        if cursor.key.is_empty() || cursor.id.is_empty() {
+            return Err(InvalidCursor);
+        }
+
+        Ok(cursor)
+    }
+
+    /// Decodes an optional `cursor` query parameter, treating an empty string as absent.
+    pub fn from_query(cursor: Option<&str>) -> Result<Option<Self>, InvalidCursor> {
+        match cursor {
+            Some(c) if !c.is_empty() => Self::decode(c).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn key(cursor: Option<&Cursor>) -> Option<String> {
+        cursor.map(|c| c.key.clone())
+    }
+
+    pub fn id(cursor: Option<&Cursor>) -> Option<String> {
+        cursor.map(|c| c.id.clone())
+    }
+}
+
+/// Clamps a requested page size to `1..=MAX_PAGE_SIZE`.
+pub fn page_size(count: Option<i32>) -> i32 {
+    count.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Builds a page from up to `limit + 1` rows. The extra row only signals
+    /// that another page exists and is dropped; the cursor points at the last
+    /// row that is returned.
+    pub fn from_rows(mut items: Vec<T>, limit: i32, cursor: impl Fn(&T) -> Cursor) -> Self {
+        let limit = limit.max(0) as usize;
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|last| cursor(last).encode())
+        } else {
+            None
+        };
+
+        Self { items, next_cursor }
+    }
+}
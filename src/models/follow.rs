@@ -0,0 +1,182 @@
+use libsql::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use super::cursor::{Cursor, Page};
+use crate::error::AppError;
+
+pub struct Follow;
+
+impl Follow {
+    /// Returns `false` when `follower` was already following `followed`.
+    pub async fn insert_into_db(
+        follower: &str,
+        followed: &str,
+        conn: &Connection,
+    ) -> Result<bool, AppError> {
+        let tran = conn.transaction().await?;
+        let inserted = tran
+            .execute(
+                r#"
+            INSERT OR IGNORE INTO followers (follower_id, followed_id)
+            VALUES (?1, ?2)
+            "#,
+                params![follower, followed],
+            )
+            .await?;
+
+        // Each count only moves if the statement before it changed a row,
+        // so following someone twice leaves both alone.
+        tran.execute(
+            r#"
+            UPDATE users
+            SET following = following + 1
+            WHERE id = ?1 AND changes() > 0
+            "#,
+            params![follower],
+        )
+        .await?;
+
+        tran.execute(
+            r#"
+            UPDATE users
+            SET followers = followers + 1
+            WHERE id = ?1 AND changes() > 0
+            "#,
+            params![followed],
+        )
+        .await?;
+
+        tran.commit().await?;
+        Ok(inserted > 0)
+    }
+
+    /// Returns `false` when `follower` was not following `followed`.
+    pub async fn delete_from_db(
+        follower: &str,
+        followed: &str,
+        conn: &Connection,
+    ) -> Result<bool, AppError> {
+        let tran = conn.transaction().await?;
+        let deleted = tran
+            .execute(
+                r#"
+            DELETE FROM followers
+            WHERE follower_id = ?1 AND followed_id = ?2
+            "#,
+                params![follower, followed],
+            )
+            .await?;
+
+        tran.execute(
+            r#"
+            UPDATE users
+            SET following = MAX(following - 1, 0)
+            WHERE id = ?1 AND changes() > 0
+            "#,
+            params![follower],
+        )
+        .await?;
+
+        tran.execute(
+            r#"
+            UPDATE users
+            SET followers = MAX(followers - 1, 0)
+            WHERE id = ?1 AND changes() > 0
+            "#,
+            params![followed],
+        )
+        .await?;
+
+        tran.commit().await?;
+        Ok(deleted > 0)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetrieveFollow {
+    pub id: String,
+    pub username: String,
+    pub first_name: String,
+    pub last_name: String,
+}
+
+impl RetrieveFollow {
+    /// Users following `user`, ordered by username.
+    pub async fn followers_from_db(
+        user: &str,
+        conn: &Connection,
+        limit: i32,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<RetrieveFollow>, AppError> {
+        Self::query(
+            r#"
+            SELECT users.id, users.username, users.first_name, users.last_name
+            FROM followers
+            INNER JOIN users ON users.id = followers.follower_id
+            WHERE followers.followed_id = ?1
+            AND (?3 IS NULL OR (users.username, users.id) > (?3, ?4))
+            ORDER BY users.username, users.id
+            LIMIT ?2
+            "#,
+            user,
+            conn,
+            limit,
+            cursor,
+        )
+        .await
+    }
+
+    /// Users that `user` follows, ordered by username.
+    pub async fn following_from_db(
+        user: &str,
+        conn: &Connection,
+        limit: i32,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<RetrieveFollow>, AppError> {
+        Self::query(
+            r#"
+            SELECT users.id, users.username, users.first_name, users.last_name
+            FROM followers
+            INNER JOIN users ON users.id = followers.followed_id
+            WHERE followers.follower_id = ?1
+            AND (?3 IS NULL OR (users.username, users.id) > (?3, ?4))
+            ORDER BY users.username, users.id
+            LIMIT ?2
+            "#,
+            user,
+            conn,
+            limit,
+            cursor,
+        )
+        .await
+    }
+
+    async fn query(
+        sql: &str,
+        user: &str,
+        conn: &Connection,
+        limit: i32,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<RetrieveFollow>, AppError> {
+        let mut users = vec![];
+        let mut rows = conn
+            .query(
+                sql,
+                params![user, limit + 1, Cursor::key(cursor), Cursor::id(cursor)],
+            )
+            .await?;
+
+        while let Some(row) = rows.next().await? {
+            users.push(RetrieveFollow {
+                id: row.get(0)?,
+                username: row.get(1)?,
+                first_name: row.get(2)?,
+                last_name: row.get(3)?,
+            });
+        }
+
+        Ok(Page::from_rows(users, limit, |u| {
+            Cursor::new(&u.username, &u.id)
+        }))
+    }
+}
@@ -0,0 +1,319 @@
+use libsql::{params, Connection, Row};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator_derive::Validate;
+
+use crate::auth::domains::{AllowedDomain, DomainPolicy};
+use crate::error::AppError;
+
+/// Which campuses a listing covers. Listings default to the caller's campus.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    #[default]
+    Campus,
+    All,
+}
+
+impl Scope {
+    pub fn cross_campus(&self) -> bool {
+        *self == Scope::All
+    }
+}
+
+/// Picks the institution an email belongs to: the longest domain equal to the
+/// email's host or a parent of it, so `dcrustm.org` covers `cse.dcrustm.org`
+/// but not `evildcrustm.org`. `?1` is the email.
+const MATCHES_EMAIL: &str = r#"
+    WHERE LOWER(SUBSTR(?1, INSTR(?1, '@') + 1)) = institutions.domain
+    OR SUBSTR(LOWER(SUBSTR(?1, INSTR(?1, '@') + 1)), -LENGTH(institutions.domain) - 1)
+        = '.' || institutions.domain
+    ORDER BY LENGTH(institutions.domain) DESC
+    LIMIT 1
+"#;
+
+/// Outcome of deleting an institution.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Removal {
+    Deleted,
+    NotFound,
+    /// Users still belong to it.
+    InUse,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Institution {
+    pub id: String,
+    pub name: String,
+    pub domain: String,
+    pub roll_format: Option<String>,
+    pub created_at: String,
+}
+
+impl TryFrom<Row> for Institution {
+    type Error = AppError;
+
+    fn try_from(row: Row) -> Result<Self, Self::Error> {
+        Ok(Institution {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            domain: row.get(2)?,
+            roll_format: row.get::<Option<String>>(3)?,
+            created_at: row.get(4)?,
+        })
+    }
+}
+
+impl Institution {
+    /// Adds an institution for every configured domain, or brings its roll
+    /// number format in line with the config, then assigns users that have no
+    /// institution by their email.
+    ///
+    /// The institutions table decides who can register. A domain dropped from
+    /// the config keeps its institution until an admin deletes it, so each
+    /// one is logged.
+    pub async fn seed(policy: &DomainPolicy, conn: &Connection) -> Result<(), AppError> {
+        let tran = conn.transaction().await?;
+        for domain in policy.domains() {
+            tran.execute(
+                r#"
+                INSERT INTO institutions (id, name, domain, roll_format)
+                VALUES (?1, ?2, ?2, ?3)
+                ON CONFLICT(domain) DO UPDATE SET roll_format = excluded.roll_format
+                "#,
+                params![
+                    Uuid::new_v4().to_string(),
+                    domain.domain.clone(),
+                    domain.roll_format().map(str::to_string)
+                ],
+            )
+            .await?;
+        }
+
+        tran.execute(
+            &format!(
+                r#"
+                UPDATE users
+                SET institution_id = (SELECT institutions.id FROM institutions {})
+                WHERE institution_id IS NULL
+                "#,
+                MATCHES_EMAIL.replace("?1", "users.email")
+            ),
+            (),
+        )
+        .await?;
+
+        tran.commit().await?;
+
+        let institutions = Self::all_from_db(conn).await?;
+        info!(
+            "Registration is open to {} institutions; ALLOWED_EMAIL_DOMAINS only adds or updates them",
+            institutions.len()
+        );
+        for institution in institutions
+            .iter()
+            .filter(|i| !policy.domains().iter().any(|d| d.domain == i.domain))
+        {
+            warn!(
+                "{} is not in ALLOWED_EMAIL_DOMAINS but stays open until its institution is deleted",
+                institution.domain
+            );
+        }
+        Ok(())
+    }
+
+    /// The institution `email` belongs to, if registration is open for it.
+    pub async fn for_email(email: &str, conn: &Connection) -> Result<Option<Self>, AppError> {
+        let mut rows = conn
+            .query(
+                &format!(
+                    "SELECT id, name, domain, roll_format, created_at FROM institutions {}",
+                    MATCHES_EMAIL
+                ),
+                params![email],
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(Institution::try_from(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `for_email`, but an email outside every institution is an error.
+    pub async fn require_for_email(email: &str, conn: &Connection) -> Result<Self, AppError> {
+        Self::for_email(email, conn).await?.ok_or_else(|| {
+            AppError::BadRequest(
+                "email_domain_not_allowed",
+                "Registration is not open for this email domain".to_string(),
+            )
+        })
+    }
+
+    /// Checks `roll` follows this institution's roll number format.
+    pub fn check_roll(&self, roll: &str) -> Result<(), AppError> {
+        let domain = AllowedDomain::new(&self.domain, self.roll_format.as_deref())
+            .map_err(AppError::internal)?;
+        if !domain.roll_is_valid(roll) {
+            return Err(AppError::BadRequest(
+                "invalid_roll",
+                format!("Roll number is not in the format used by {}", self.name),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn all_from_db(conn: &Connection) -> Result<Vec<Self>, AppError> {
+        let mut rows = conn
+            .query(
+                "SELECT id, name, domain, roll_format, created_at FROM institutions ORDER BY name, id",
+                (),
+            )
+            .await?;
+
+        let mut institutions = vec![];
+        while let Some(row) = rows.next().await? {
+            institutions.push(Institution::try_from(row)?);
+        }
+        Ok(institutions)
+    }
+
+    /// Whether two users belong to the same institution.
+    pub async fn same_campus(a: &str, b: &str, conn: &Connection) -> Result<bool, AppError> {
+        let mut rows = conn
+            .query(
+                r#"
+            SELECT 1 FROM users AS a, users AS b
+            WHERE a.id = ?1 AND b.id = ?2 AND a.institution_id IS b.institution_id
+            "#,
+                params![a, b],
+            )
+            .await?;
+
+        Ok(rows.next().await?.is_some())
+    }
+
+    /// Only institutions nobody belongs to can be deleted.
+    pub async fn delete_from_db(id: &str, conn: &Connection) -> Result<Removal, AppError> {
+        let deleted = conn
+            .execute(
+                r#"
+            DELETE FROM institutions
+            WHERE id = ?1 AND NOT EXISTS (SELECT 1 FROM users WHERE institution_id = ?1)
+            "#,
+                params![id],
+            )
+            .await?;
+
+        if deleted > 0 {
+            return Ok(Removal::Deleted);
+        }
+
+        Ok(match retrieve(id, conn).await? {
+            Some(_) => Removal::InUse,
+            None => Removal::NotFound,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateInstitution {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    #[validate(length(min = 3, max = 253))]
+    pub domain: String,
+    pub roll_format: Option<String>,
+}
+
+impl CreateInstitution {
+    pub async fn insert_into_db(&self, conn: &Connection) -> Result<Institution, AppError> {
+        let domain = allowed_domain(&self.domain, self.roll_format.as_deref())?;
+        let id = Uuid::new_v4().to_string();
+
+        let inserted = conn
+            .execute(
+                r#"
+            INSERT OR IGNORE INTO institutions (id, name, domain, roll_format)
+            VALUES (?1, ?2, ?3, ?4)
+            "#,
+                params![
+                    id.clone(),
+                    self.name.clone(),
+                    domain.domain.clone(),
+                    self.roll_format.clone()
+                ],
+            )
+            .await?;
+
+        if inserted == 0 {
+            return Err(AppError::Conflict(
+                "domain_taken",
+                "An institution already uses this domain".to_string(),
+            ));
+        }
+
+        retrieve(&id, conn)
+            .await?
+            .ok_or_else(|| AppError::internal("institution missing after insert"))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateInstitution {
+    #[validate(length(min = 1, max = 100))]
+    pub name: Option<String>,
+    /// An empty string removes the roll number format.
+    pub roll_format: Option<String>,
+}
+
+impl UpdateInstitution {
+    /// Returns `None` when there is no institution with this id.
+    pub async fn update_into_db(
+        &self,
+        id: &str,
+        conn: &Connection,
+    ) -> Result<Option<Institution>, AppError> {
+        let Some(current) = retrieve(id, conn).await? else {
+            return Ok(None);
+        };
+
+        let roll_format = match self.roll_format.as_deref() {
+            Some("") => None,
+            Some(format) => Some(format.to_string()),
+            None => current.roll_format,
+        };
+        allowed_domain(&current.domain, roll_format.as_deref())?;
+
+        conn.execute(
+            r#"
+            UPDATE institutions
+            SET name = ?1, roll_format = ?2
+            WHERE id = ?3
+            "#,
+            params![self.name.clone().unwrap_or(current.name), roll_format, id],
+        )
+        .await?;
+
+        retrieve(id, conn).await
+    }
+}
+
+async fn retrieve(id: &str, conn: &Connection) -> Result<Option<Institution>, AppError> {
+    let mut rows = conn
+        .query(
+            "SELECT id, name, domain, roll_format, created_at FROM institutions WHERE id = ?1",
+            params![id],
+        )
+        .await?;
+
+    match rows.next().await? {
+        Some(row) => Ok(Some(Institution::try_from(row)?)),
+        None => Ok(None),
+    }
+}
+
+/// Validates a domain and roll number format supplied through the API.
+fn allowed_domain(domain: &str, roll_format: Option<&str>) -> Result<AllowedDomain, AppError> {
+    AllowedDomain::new(domain, roll_format)
+        .map_err(|e| AppError::BadRequest("invalid_institution", e.to_string()))
+}
@@ -1,6 +1,10 @@
-
 pub mod user;
 pub mod otp;
 pub mod post;
 pub mod comment;
-pub mod profile;
\ No newline at end of file
+pub mod profile;
+pub mod follow;
+pub mod cursor;
+pub mod institution;
+pub mod session;
+pub mod outbox;
\ No newline at end of file
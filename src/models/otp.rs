@@ -1,8 +1,58 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use validator_derive::Validate;
 
-use libsql::Row;
+use libsql::{params, Connection, Row};
+
+use crate::error::AppError;
+
+/// How long an OTP can be used after it is sent.
+pub const OTP_TTL_MINUTES: i64 = 5;
+/// Wrong guesses allowed against a single code before it is locked.
+pub const MAX_OTP_ATTEMPTS: u32 = 5;
+/// How long a code stays locked once `MAX_OTP_ATTEMPTS` is reached. No new
+/// code can be sent for the same email and purpose in that time.
+pub const OTP_LOCKOUT_MINUTES: i64 = 15;
+/// Minimum time between two codes sent for the same email and purpose.
+pub const OTP_RESEND_COOLDOWN_SECS: i64 = 60;
+
+/// What an OTP was issued for. A code is only accepted for the purpose it
+/// was sent for. The `purpose` column is plain text, so new flows only need a
+/// new variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpPurpose {
+    Registration,
+    PasswordReset,
+}
+
+impl OtpPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OtpPurpose::Registration => "registration",
+            OtpPurpose::PasswordReset => "password_reset",
+        }
+    }
+}
+
+/// Outcome of asking for a new code.
+#[derive(Debug, PartialEq, Eq)]
+pub enum OtpRequest {
+    Issued,
+    /// A code was sent too recently. Carries the seconds left to wait.
+    TooSoon(i64),
+    /// Too many wrong guesses. Carries the seconds left until the lock ends.
+    Locked(i64),
+}
+
+/// Outcome of checking a code.
+#[derive(Debug, PartialEq, Eq)]
+pub enum OtpCheck {
+    Valid,
+    NotFound,
+    Expired,
+    Invalid { remaining: u32 },
+    Locked(i64),
+}
 
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct Otp {
@@ -13,8 +63,180 @@ pub struct Otp {
     pub created_at: NaiveDateTime,
 }
 
+impl Otp {
+    /// Stores `otp` for `email`, replacing any earlier code for the same
+    /// purpose unless that code is locked or was sent too recently.
+    pub async fn insert_into_db(
+        email: &str,
+        otp: &str,
+        purpose: OtpPurpose,
+        conn: &Connection,
+    ) -> Result<OtpRequest, AppError> {
+        let now = Utc::now();
+        let issued = conn
+            .execute(
+                r#"
+            INSERT INTO otps (email, purpose, otp, created_at)
+            VALUES (?1, ?2, ?3, CURRENT_TIMESTAMP)
+            ON CONFLICT(email, purpose) DO UPDATE
+            SET otp = ?3, created_at = CURRENT_TIMESTAMP, attempts = 0, locked_until = NULL
+            WHERE COALESCE(locked_until, 0) <= ?4 AND created_at <= datetime('now', ?5)
+            "#,
+                params![
+                    email,
+                    purpose.as_str(),
+                    otp,
+                    now.timestamp(),
+                    format!("-{} seconds", OTP_RESEND_COOLDOWN_SECS)
+                ],
+            )
+            .await?;
+
+        if issued > 0 {
+            return Ok(OtpRequest::Issued);
+        }
+
+        // The code already there held this one back.
+        let mut rows = conn
+            .query(
+                "SELECT created_at, locked_until FROM otps WHERE email = ?1 AND purpose = ?2",
+                params![email, purpose.as_str()],
+            )
+            .await?;
+
+        Ok(match rows.next().await? {
+            Some(row) => {
+                let created_at = parse_timestamp(&row.get::<String>(0)?)?;
+                let locked_until = row.get::<Option<i64>>(1)?.unwrap_or(0);
+                let wait =
+                    (created_at + Duration::seconds(OTP_RESEND_COOLDOWN_SECS) - now).num_seconds();
+
+                if locked_until > now.timestamp() {
+                    OtpRequest::Locked(locked_until - now.timestamp())
+                } else {
+                    OtpRequest::TooSoon(wait.max(1))
+                }
+            }
+            // Used or withdrawn in the meantime, so asking again will work.
+            None => OtpRequest::TooSoon(1),
+        })
+    }
+
+    /// Deletes `otp` if it is still the current code, for when it could not
+    /// be sent. Otherwise the cooldown would hold back a code that never
+    /// arrived.
+    pub async fn withdraw(
+        email: &str,
+        otp: &str,
+        purpose: OtpPurpose,
+        conn: &Connection,
+    ) -> Result<(), AppError> {
+        conn.execute(
+            "DELETE FROM otps WHERE email = ?1 AND purpose = ?2 AND otp = ?3",
+            params![email, purpose.as_str(), otp],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Checks `code` against the stored OTP. A correct code is deleted so it
+    /// can only be used once; a wrong one counts towards the lockout.
+    pub async fn verify(
+        email: &str,
+        purpose: OtpPurpose,
+        code: &str,
+        conn: &Connection,
+    ) -> Result<OtpCheck, AppError> {
+        let now = Utc::now();
+        let unexpired = format!("-{} minutes", OTP_TTL_MINUTES);
+
+        // A correct code that is neither locked nor expired is used up.
+        let used = conn
+            .execute(
+                r#"
+            DELETE FROM otps
+            WHERE email = ?1 AND purpose = ?2 AND otp = ?3
+            AND COALESCE(locked_until, 0) <= ?4 AND created_at > datetime('now', ?5)
+            "#,
+                params![
+                    email,
+                    purpose.as_str(),
+                    code,
+                    now.timestamp(),
+                    unexpired.clone()
+                ],
+            )
+            .await?;
+
+        if used > 0 {
+            return Ok(OtpCheck::Valid);
+        }
+
+        // A wrong one counts towards the lockout.
+        let mut rows = conn
+            .query(
+                r#"
+            UPDATE otps
+            SET attempts = attempts + 1,
+                locked_until = CASE WHEN attempts + 1 >= ?4 THEN ?5 END
+            WHERE email = ?1 AND purpose = ?2 AND otp != ?3
+            AND COALESCE(locked_until, 0) <= ?6 AND created_at > datetime('now', ?7)
+            RETURNING attempts, locked_until
+            "#,
+                params![
+                    email,
+                    purpose.as_str(),
+                    code,
+                    MAX_OTP_ATTEMPTS,
+                    (now + Duration::minutes(OTP_LOCKOUT_MINUTES)).timestamp(),
+                    now.timestamp(),
+                    unexpired
+                ],
+            )
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            let attempts = row.get::<u32>(0)?;
+            return Ok(match row.get::<Option<i64>>(1)? {
+                Some(until) => OtpCheck::Locked(until - now.timestamp()),
+                None => OtpCheck::Invalid {
+                    remaining: MAX_OTP_ATTEMPTS - attempts,
+                },
+            });
+        }
+        drop(rows);
+
+        // Neither, so the code is missing, locked or expired.
+        let mut rows = conn
+            .query(
+                "SELECT locked_until FROM otps WHERE email = ?1 AND purpose = ?2",
+                params![email, purpose.as_str()],
+            )
+            .await?;
+
+        Ok(match rows.next().await? {
+            None => OtpCheck::NotFound,
+            Some(row) => {
+                let locked_until = row.get::<Option<i64>>(0)?.unwrap_or(0);
+                if locked_until > now.timestamp() {
+                    OtpCheck::Locked(locked_until - now.timestamp())
+                } else {
+                    OtpCheck::Expired
+                }
+            }
+        })
+    }
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, AppError> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+        .map(|t| t.and_utc())
+        .map_err(AppError::internal)
+}
+
 impl TryFrom<Row> for Otp {
-    type Error = anyhow::Error;
+    type Error = AppError;
 
     fn try_from(row: Row) -> Result<Self, Self::Error> {
         // Extract values from the row
@@ -23,8 +245,7 @@ impl TryFrom<Row> for Otp {
 
         // Convert timestamp to NaiveDateTime
         let created_at_str: String = row.get(2)?;
-        log::info!("created_at_str: {}", created_at_str);
-        let created_at = NaiveDateTime::parse_from_str(&created_at_str, "%Y-%m-%d %H:%M:%S")?;
+        let created_at = parse_timestamp(&created_at_str)?.naive_utc();
 
         Ok(Otp {
             email,
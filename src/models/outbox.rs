@@ -0,0 +1,307 @@
+use chrono::Utc;
+use lettre::{address::Envelope, Address, Message};
+use libsql::{params, Connection};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::cursor::{Cursor, Page};
+use crate::error::AppError;
+
+/// Sends are given up on after this many failures, about two hours after
+/// the first one.
+pub const MAX_ATTEMPTS: i64 = 8;
+/// Wait before the first retry, doubled after every further failure.
+const RETRY_DELAY_SECS: i64 = 60;
+/// How long a claimed message is left alone before another worker may pick
+/// it up again, in case the one sending it died.
+const CLAIM_SECS: i64 = 5 * 60;
+
+pub struct QueueEmail<'a> {
+    pub subject: &'a str,
+    pub message: &'a Message,
+}
+
+impl QueueEmail<'_> {
+    /// Queues the message for the delivery worker to send straight away.
+    pub async fn insert_into_db(&self, conn: &Connection) -> Result<(), AppError> {
+        let envelope = self.message.envelope();
+        let sender = envelope.from().map(|a| a.to_string()).unwrap_or_default();
+        let recipients = envelope
+            .to()
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        conn.execute(
+            r#"
+            INSERT INTO email_outbox (id, sender, recipients, subject, message, next_attempt_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+            params![
+                Uuid::new_v4().to_string(),
+                sender,
+                recipients,
+                self.subject,
+                self.message.formatted(),
+                Utc::now().timestamp()
+            ],
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// A message claimed for delivery.
+pub struct DueEmail {
+    pub id: String,
+    pub envelope: Envelope,
+    pub message: Vec<u8>,
+    pub attempts: i64,
+}
+
+/// What happened to a message that could not be sent.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendFailure {
+    /// It will be tried again after this many seconds.
+    Retry(i64),
+    GaveUp,
+}
+
+impl DueEmail {
+    /// Claims up to `limit` messages that are due. Claimed messages aren't
+    /// handed out again until `CLAIM_SECS` have passed.
+    pub async fn claim(conn: &Connection, limit: i64) -> Result<Vec<DueEmail>, AppError> {
+        let now = Utc::now().timestamp();
+        let mut rows = conn
+            .query(
+                r#"
+            UPDATE email_outbox SET next_attempt_at = ?2
+            WHERE id IN (
+                SELECT id FROM email_outbox
+                WHERE sent_at IS NULL AND failed_at IS NULL AND next_attempt_at <= ?1
+                ORDER BY next_attempt_at
+                LIMIT ?3
+            )
+            RETURNING id, sender, recipients, message, attempts
+            "#,
+                params![now, now + CLAIM_SECS, limit],
+            )
+            .await?;
+
+        let mut due = vec![];
+        while let Some(row) = rows.next().await? {
+            let sender: String = row.get(1)?;
+            let recipients: String = row.get(2)?;
+            let envelope = Envelope::new(
+                sender.parse::<Address>().ok(),
+                recipients
+                    .split(',')
+                    .map(|r| r.parse::<Address>())
+                    .collect::<Result<_, _>>()
+                    .map_err(AppError::internal)?,
+            )
+            .map_err(AppError::internal)?;
+
+            due.push(DueEmail {
+                id: row.get(0)?,
+                envelope,
+                message: row.get(3)?,
+                attempts: row.get(4)?,
+            });
+        }
+
+        Ok(due)
+    }
+
+    /// Records the delivery. The message itself is dropped since it may hold
+    /// a one-time code and won't be needed again.
+    pub async fn mark_sent(&self, conn: &Connection) -> Result<(), AppError> {
+        conn.execute(
+            r#"
+            UPDATE email_outbox
+            SET sent_at = CURRENT_TIMESTAMP, attempts = attempts + 1, last_error = NULL,
+                message = X''
+            WHERE id = ?1
+            "#,
+            params![self.id.clone()],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed send and schedules the next attempt, unless this was
+    /// the last one.
+    pub async fn mark_failed(
+        &self,
+        error: &str,
+        conn: &Connection,
+    ) -> Result<SendFailure, AppError> {
+        let attempts = self.attempts + 1;
+        let outcome = if attempts >= MAX_ATTEMPTS {
+            SendFailure::GaveUp
+        } else {
+            SendFailure::Retry(RETRY_DELAY_SECS << (attempts - 1))
+        };
+
+        let next_attempt_at = match outcome {
+            SendFailure::Retry(delay) => Utc::now().timestamp() + delay,
+            SendFailure::GaveUp => Utc::now().timestamp(),
+        };
+        conn.execute(
+            r#"
+            UPDATE email_outbox
+            SET attempts = ?2, last_error = ?3, next_attempt_at = ?4,
+                failed_at = CASE WHEN ?5 THEN CURRENT_TIMESTAMP END
+            WHERE id = ?1
+            "#,
+            params![
+                self.id.clone(),
+                attempts,
+                error,
+                next_attempt_at,
+                outcome == SendFailure::GaveUp
+            ],
+        )
+        .await?;
+
+        Ok(outcome)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxStatus {
+    /// Not sent yet, including messages waiting for a retry.
+    Pending,
+    Sent,
+    /// Given up on after `MAX_ATTEMPTS` failures.
+    Failed,
+}
+
+impl OutboxStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OutboxStatus::Pending => "pending",
+            OutboxStatus::Sent => "sent",
+            OutboxStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub id: String,
+    pub recipients: Vec<String>,
+    pub subject: String,
+    pub status: OutboxStatus,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    /// Unix time of the next attempt, for pending messages.
+    pub next_attempt_at: Option<i64>,
+    pub sent_at: Option<String>,
+    pub failed_at: Option<String>,
+    pub created_at: String,
+}
+
+impl OutboxEntry {
+    /// Queued messages, newest first, optionally only those in `status`.
+    pub async fn retrieve_from_db(
+        status: Option<OutboxStatus>,
+        conn: &Connection,
+        limit: i32,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<OutboxEntry>, AppError> {
+        let mut rows = conn
+            .query(
+                r#"
+            SELECT id, recipients, subject, attempts, last_error, next_attempt_at,
+                sent_at, failed_at, created_at,
+                CASE
+                    WHEN sent_at IS NOT NULL THEN 'sent'
+                    WHEN failed_at IS NOT NULL THEN 'failed'
+                    ELSE 'pending'
+                END AS status
+            FROM email_outbox
+            WHERE (?4 IS NULL OR status = ?4)
+            AND (?2 IS NULL OR (created_at, id) < (?2, ?3))
+            ORDER BY created_at DESC, id DESC
+            LIMIT ?1
+            "#,
+                params![
+                    limit + 1,
+                    Cursor::key(cursor),
+                    Cursor::id(cursor),
+                    status.map(|s| s.as_str())
+                ],
+            )
+            .await?;
+
+        let mut entries = vec![];
+        while let Some(row) = rows.next().await? {
+            let status = match row.get::<String>(9)?.as_str() {
+                "sent" => OutboxStatus::Sent,
+                "failed" => OutboxStatus::Failed,
+                _ => OutboxStatus::Pending,
+            };
+            entries.push(OutboxEntry {
+                id: row.get(0)?,
+                recipients: row
+                    .get::<String>(1)?
+                    .split(',')
+                    .map(str::to_string)
+                    .collect(),
+                subject: row.get(2)?,
+                status,
+                attempts: row.get(3)?,
+                last_error: row.get(4)?,
+                next_attempt_at: match status {
+                    OutboxStatus::Pending => Some(row.get(5)?),
+                    _ => None,
+                },
+                sent_at: row.get(6)?,
+                failed_at: row.get(7)?,
+                created_at: row.get(8)?,
+            });
+        }
+
+        Ok(Page::from_rows(entries, limit, |e| {
+            Cursor::new(&e.created_at, &e.id)
+        }))
+    }
+
+    /// Deletes messages that were sent or given up on more than
+    /// `max_age_days` ago. Returns how many were removed.
+    pub async fn prune(max_age_days: i64, conn: &Connection) -> Result<u64, AppError> {
+        let pruned = conn
+            .execute(
+                r#"
+            DELETE FROM email_outbox
+            WHERE COALESCE(sent_at, failed_at) < datetime('now', ?1)
+            "#,
+                params![format!("-{} days", max_age_days)],
+            )
+            .await?;
+
+        Ok(pruned)
+    }
+
+    /// Puts a message that was given up on back in the queue with a fresh
+    /// set of attempts. Returns `false` if there is no such failed message.
+    pub async fn retry(id: &str, conn: &Connection) -> Result<bool, AppError> {
+        let updated = conn
+            .execute(
+                r#"
+            UPDATE email_outbox
+            SET failed_at = NULL, attempts = 0, next_attempt_at = ?2
+            WHERE id = ?1 AND failed_at IS NOT NULL
+            "#,
+                params![id, Utc::now().timestamp()],
+            )
+            .await?;
+
+        Ok(updated > 0)
+    }
+}
@@ -1,16 +1,57 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 use libsql::{params, Connection, Transaction};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use uuid::Uuid;
 use validator_derive::Validate;
 
+use super::cursor::{Cursor, Page};
+use super::institution::Scope;
+use crate::error::AppError;
+
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CreatePost {
     #[validate(length(max = 1000))]
     pub text: String,
     pub public: bool,
-    // pub images: Vec<CreatePostImage>
+    #[serde(default)]
+    #[validate(length(max = 10, message = "A post can have at most 10 images"))]
+    pub images: Vec<PostImageDescriptor>,
+}
+
+/// An image the client intends to upload alongside a new post.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PostImageDescriptor {
+    pub content_type: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct EditPost {
+    #[validate(length(max = 1000))]
+    pub text: Option<String>,
+    pub public: Option<bool>,
+}
+
+/// Outcome of an operation that only the owner of a post may perform.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ownership {
+    Owner,
+    NotOwner,
+    NotFound,
+}
+
+impl Ownership {
+    async fn check(user: &str, post: &str, conn: &Connection) -> Result<Ownership, AppError> {
+        let mut rows = conn
+            .query("SELECT user FROM posts WHERE id = ?1", params![post])
+            .await?;
+
+        Ok(match rows.next().await? {
+            Some(row) if row.get::<String>(0)? == user => Ownership::Owner,
+            Some(_) => Ownership::NotOwner,
+            None => Ownership::NotFound,
+        })
+    }
 }
 
 #[derive(Debug, Validate)]
@@ -40,10 +81,10 @@ impl<'de> Deserialize<'de> for CreatePostImage {
 impl CreatePost {
     pub async fn insert_into_db(
         &self,
-        user: &String,
-        uuid: &String,
+        user: &str,
+        uuid: &str,
         conn: &Connection,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), AppError> {
         let text = self.text.clone();
         let public = self.public;
 
@@ -52,7 +93,17 @@ impl CreatePost {
             INSERT INTO posts (id, user, text, public)
             VALUES (?1, ?2, ?3, ?4)
             "#,
-            params![uuid.to_string(), user.clone(), text, public],
+            params![uuid.to_string(), user, text, public],
+        )
+        .await?;
+
+        conn.execute(
+            r#"
+            UPDATE users
+            SET posts = posts + 1
+            WHERE id = ?1
+            "#,
+            params![user],
         )
         .await?;
 
@@ -63,23 +114,58 @@ impl CreatePost {
 impl CreatePostImage {
     pub async fn insert_into_db(
         &self,
-        id: &String,
-        post: &String,
+        id: &str,
+        post: &str,
         conn: &Transaction,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), AppError> {
         let image_url = self.image.clone();
         conn.execute(
             r#"
             INSERT INTO post_images (id, post, image_url)
             VALUES (?1, ?2, ?3)
             "#,
-            params![id.clone(), post.clone(), image_url],
+            params![id, post, image_url],
         )
         .await?;
         Ok(())
     }
 }
 
+pub struct PostImages;
+
+impl PostImages {
+    /// Image URLs for each of `posts`, keyed by post id.
+    pub async fn retrieve_from_db(
+        posts: &[&str],
+        conn: &Connection,
+    ) -> Result<HashMap<String, Vec<String>>, AppError> {
+        let mut images: HashMap<String, Vec<String>> = HashMap::new();
+        if posts.is_empty() {
+            return Ok(images);
+        }
+
+        let mut rows = conn
+            .query(
+                r#"
+            SELECT post, image_url
+            FROM post_images
+            WHERE post IN (SELECT value FROM json_each(?1))
+            ORDER BY rowid
+            "#,
+                params![serde_json::to_string(posts)?],
+            )
+            .await?;
+
+        while let Some(row) = rows.next().await? {
+            let post: String = row.get(0)?;
+            let image_url: String = row.get(1)?;
+            images.entry(post).or_default().push(image_url);
+        }
+
+        Ok(images)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RetrieveOtherPost {
     pub id: String,
@@ -89,16 +175,21 @@ pub struct RetrieveOtherPost {
     pub likes: u32,
     pub comments: u32,
     pub text: String,
-    // pub images: Vec<String>,
+    pub images: Vec<String>,
     pub created_at: String,
+    pub edited_at: Option<String>,
+    pub liked_by_me: bool,
 }
 
 impl RetrieveOtherPost {
+    /// Public posts from the campus of `user`, or from every campus.
     pub async fn retrieve_from_db(
-        user: &String,
+        user: &str,
+        scope: Scope,
         conn: &Connection,
         limit: i32,
-    ) -> Result<Vec<RetrieveOtherPost>, Box<dyn std::error::Error>> {
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<RetrieveOtherPost>, AppError> {
         let mut posts = vec![];
         // let mut stmt = conn.prepare(
         //     r#"
@@ -113,16 +204,27 @@ impl RetrieveOtherPost {
 
         let mut stmt = conn.prepare(
             r#"
-                SELECT posts.id, posts.user, users.username, posts.likes, posts.comments, posts.text, posts.created_at
+                SELECT posts.id, posts.user, users.username, posts.likes, posts.comments, posts.text, posts.created_at, posts.edited_at,
+                    EXISTS(SELECT 1 FROM post_likes WHERE post_likes.post = posts.id AND post_likes.user = ?4)
                 FROM users 
                 INNER JOIN posts ON users.id = posts.user
                 WHERE posts.public = true
-                ORDER BY posts.created_at DESC
+                AND (?5 OR users.institution_id IS (SELECT institution_id FROM users WHERE id = ?4))
+                AND (?2 IS NULL OR (posts.created_at, posts.id) < (?2, ?3))
+                ORDER BY posts.created_at DESC, posts.id DESC
                 LIMIT ?1
             "#
         ).await?;
 
-        let mut rows = stmt.query(params![limit]).await?;
+        let mut rows = stmt
+            .query(params![
+                limit + 1,
+                Cursor::key(cursor),
+                Cursor::id(cursor),
+                user,
+                scope.cross_campus()
+            ])
+            .await?;
         while let Some(row) = rows.next().await? {
             let id: String = row.get(0)?;
             let user: String = row.get(1)?;
@@ -132,6 +234,8 @@ impl RetrieveOtherPost {
             let comments: u32 = row.get(4)?;
             let text: String = row.get(5)?;
             let created_at: String = row.get(6)?;
+            let edited_at: Option<String> = row.get::<Option<String>>(7)?;
+            let liked_by_me: bool = row.get(8)?;
 
             posts.push(RetrieveOtherPost {
                 id,
@@ -141,98 +245,277 @@ impl RetrieveOtherPost {
                 likes,
                 comments,
                 text,
-                // images,
+                images: vec![],
                 created_at,
+                edited_at,
+                liked_by_me,
             });
         }
 
-        Ok(posts)
+        let mut page = Page::from_rows(posts, limit, |p| Cursor::new(&p.created_at, &p.id));
+        let ids: Vec<&str> = page.items.iter().map(|p| p.id.as_str()).collect();
+        let mut images = PostImages::retrieve_from_db(&ids, conn).await?;
+        for post in page.items.iter_mut() {
+            post.images = images.remove(&post.id).unwrap_or_default();
+        }
+
+        Ok(page)
     }
 }
 
+/// Result of liking or unliking a post. Repeating either is a no-op.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LikeChange {
+    Changed,
+    Unchanged,
+    PostNotFound,
+}
+
 pub struct LikePost;
 
 impl LikePost {
     pub async fn insert_into_db(
-        user: &String,
-        post: &String,
+        user: &str,
+        post: &str,
         conn: &Connection,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<LikeChange, AppError> {
         let tran = conn.transaction().await?;
-        tran.execute(
-            r#"
-            INSERT INTO post_likes (id, post, user)
-            VALUES (?1, ?2, ?3)
+        let inserted = tran
+            .execute(
+                r#"
+            INSERT OR IGNORE INTO post_likes (id, post, user)
+            SELECT ?1, id, ?3 FROM posts WHERE id = ?2
             "#,
-            params![Uuid::new_v4().to_string(), post.clone(), user.clone()],
-        )
-        .await?;
+                params![Uuid::new_v4().to_string(), post, user],
+            )
+            .await?;
 
         tran.execute(
             r#"
             UPDATE posts
             SET likes = likes + 1
-            WHERE id = ?1
+            WHERE id = ?1 AND changes() > 0
             "#,
-            params![post.clone()],
+            params![post],
         )
         .await?;
 
         tran.commit().await?;
-        Ok(())
+        Self::outcome(inserted, post, conn).await
     }
-}
-
-pub struct DeletePost;
 
-impl DeletePost {
     pub async fn delete_from_db(
-        user: &String,
-        post: &String,
+        user: &str,
+        post: &str,
         conn: &Connection,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<LikeChange, AppError> {
         let tran = conn.transaction().await?;
-        tran.execute(
-            r#"
-            DELETE FROM posts
-            WHERE id = ?1 AND user = ?2
+        let deleted = tran
+            .execute(
+                r#"
+            DELETE FROM post_likes
+            WHERE post = ?1 AND user = ?2
             "#,
-            params![post.clone(), user.clone()],
-        )
-        .await?;
+                params![post, user],
+            )
+            .await?;
 
         tran.execute(
             r#"
-            DELETE FROM post_images
-            WHERE post = ?1
+            UPDATE posts
+            SET likes = MAX(likes - 1, 0)
+            WHERE id = ?1 AND changes() > 0
             "#,
-            params![post.clone()],
+            params![post],
         )
         .await?;
 
-        tran.execute(
-            r#"
-            DELETE FROM post_likes
-            WHERE post = ?1
+        tran.commit().await?;
+        Self::outcome(deleted, post, conn).await
+    }
+
+    /// Tells an unchanged like apart from a missing post.
+    async fn outcome(changed: u64, post: &str, conn: &Connection) -> Result<LikeChange, AppError> {
+        if changed > 0 {
+            return Ok(LikeChange::Changed);
+        }
+
+        let mut rows = conn
+            .query("SELECT 1 FROM posts WHERE id = ?1", params![post])
+            .await?;
+        Ok(match rows.next().await? {
+            Some(_) => LikeChange::Unchanged,
+            None => LikeChange::PostNotFound,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetrieveLike {
+    pub id: String,
+    pub user: String,
+    pub username: String,
+    pub profile_url: Option<String>,
+    pub created_at: String,
+}
+
+impl RetrieveLike {
+    /// Users who liked `post`, most recent first.
+    pub async fn retrieve_from_db(
+        post: &str,
+        conn: &Connection,
+        limit: i32,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<RetrieveLike>, AppError> {
+        let mut likes = vec![];
+
+        let mut rows = conn
+            .query(
+                r#"
+            SELECT post_likes.id, post_likes.user, users.username, users.profile_url, post_likes.created_at
+            FROM post_likes
+            INNER JOIN users ON users.id = post_likes.user
+            WHERE post_likes.post = ?1
+            AND (?3 IS NULL OR (post_likes.created_at, post_likes.id) < (?3, ?4))
+            ORDER BY post_likes.created_at DESC, post_likes.id DESC
+            LIMIT ?2
             "#,
-            params![post.clone()],
-        )
-        .await?;
+                params![post, limit + 1, Cursor::key(cursor), Cursor::id(cursor)],
+            )
+            .await?;
 
-        tran.execute(
-            r#"
-            DELETE FROM post_comments
-            WHERE post = ?1
+        while let Some(row) = rows.next().await? {
+            likes.push(RetrieveLike {
+                id: row.get(0)?,
+                user: row.get(1)?,
+                username: row.get(2)?,
+                profile_url: row.get::<Option<String>>(3)?,
+                created_at: row.get(4)?,
+            });
+        }
+
+        Ok(Page::from_rows(likes, limit, |l| {
+            Cursor::new(&l.created_at, &l.id)
+        }))
+    }
+}
+
+impl EditPost {
+    pub async fn update_into_db(
+        &self,
+        user: &str,
+        post: &str,
+        conn: &Connection,
+    ) -> Result<Ownership, AppError> {
+        let updated = conn
+            .execute(
+                r#"
+            UPDATE posts
+            SET text = COALESCE(?1, text),
+                public = COALESCE(?2, public),
+                edited_at = CURRENT_TIMESTAMP
+            WHERE id = ?3 AND user = ?4
             "#,
-            params![post.clone()],
-        )
-        .await?;
+                params![self.text.clone(), self.public, post, user],
+            )
+            .await?;
+
+        if updated > 0 {
+            return Ok(Ownership::Owner);
+        }
+        Ownership::check(user, post, conn).await
+    }
+}
+
+pub struct DeletePost;
 
+impl DeletePost {
+    pub async fn delete_from_db(
+        user: &str,
+        post: &str,
+        conn: &Connection,
+    ) -> Result<Ownership, AppError> {
+        let tran = conn.transaction().await?;
+        let removed = remove_post(post, Some(user), &tran).await?;
         tran.commit().await?;
-        Ok(())
+
+        if removed {
+            return Ok(Ownership::Owner);
+        }
+        Ownership::check(user, post, conn).await
+    }
+
+    /// Deletes `post` whoever wrote it, for moderation. Returns `false` if
+    /// there is no such post.
+    pub async fn remove_from_db(post: &str, conn: &Connection) -> Result<bool, AppError> {
+        let tran = conn.transaction().await?;
+        let removed = remove_post(post, None, &tran).await?;
+        tran.commit().await?;
+
+        Ok(removed)
     }
 }
 
+/// Deletes `post` along with its images, likes and comments, if it was
+/// written by `owner` or `owner` is `None`. Returns `false` if nothing was
+/// deleted.
+async fn remove_post(post: &str, owner: Option<&str>, conn: &Connection) -> Result<bool, AppError> {
+    let mut rows = conn
+        .query(
+            r#"
+        DELETE FROM posts
+        WHERE id = ?1 AND (?2 IS NULL OR user = ?2)
+        RETURNING user
+        "#,
+            params![post, owner],
+        )
+        .await?;
+    let owner: String = match rows.next().await? {
+        Some(row) => row.get(0)?,
+        None => return Ok(false),
+    };
+    drop(rows);
+
+    conn.execute(
+        r#"
+        DELETE FROM post_images
+        WHERE post = ?1
+        "#,
+        params![post],
+    )
+    .await?;
+
+    conn.execute(
+        r#"
+        DELETE FROM post_likes
+        WHERE post = ?1
+        "#,
+        params![post],
+    )
+    .await?;
+
+    conn.execute(
+        r#"
+        DELETE FROM post_comments
+        WHERE post = ?1
+        "#,
+        params![post],
+    )
+    .await?;
+
+    conn.execute(
+        r#"
+        UPDATE users
+        SET posts = MAX(posts - 1, 0)
+        WHERE id = ?1
+        "#,
+        params![owner],
+    )
+    .await?;
+
+    Ok(true)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RetrieveFriendsPost {
     pub id: String,
@@ -242,60 +525,58 @@ pub struct RetrieveFriendsPost {
     pub likes: u32,
     pub comments: u32,
     pub text: String,
-    // pub images: Vec<String>,
+    pub public: bool,
+    pub images: Vec<String>,
     pub created_at: String,
+    pub edited_at: Option<String>,
+    pub liked_by_me: bool,
 }
 
 impl RetrieveFriendsPost {
+    /// Posts by everyone `user` follows, including their non-public posts.
     pub async fn retrieve_from_db(
-        user: &String,
+        user: &str,
         conn: &Connection,
         limit: i32,
-    ) -> Result<Vec<RetrieveFriendsPost>, Box<dyn std::error::Error>> {
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<RetrieveFriendsPost>, AppError> {
         let mut posts = vec![];
 
-        let mut stmt = conn.prepare(
-            r#"
-                SELECT posts.id, posts.user, users.username, posts.likes, posts.comments, posts.text, posts.created_at
-                FROM users 
-                INNER JOIN posts ON users.id = posts.user
-                WHERE posts.public = true
-                ORDER BY posts.created_at DESC
-                LIMIT ?1
-            "#
-        ).await?;
-
-        let mut frnds = conn
+        let mut stmt = conn
             .prepare(
                 r#"
-                SELECT followed_id
+                SELECT posts.id, posts.user, users.username, posts.likes, posts.comments, posts.text, posts.public, posts.created_at, posts.edited_at,
+                    EXISTS(SELECT 1 FROM post_likes WHERE post_likes.post = posts.id AND post_likes.user = ?1)
                 FROM followers
-                WHERE follower_id = ?1
+                INNER JOIN posts ON posts.user = followers.followed_id
+                INNER JOIN users ON users.id = posts.user
+                WHERE followers.follower_id = ?1
+                AND (?3 IS NULL OR (posts.created_at, posts.id) < (?3, ?4))
+                ORDER BY posts.created_at DESC, posts.id DESC
+                LIMIT ?2
             "#,
             )
             .await?;
 
-        let mut frndsMap: HashSet<String> = HashSet::new();
-        let mut frows = frnds.query(params![user.clone()]).await?;
-
-        while let Some(row) = frows.next().await? {
-            let frnd: String = row.get(0)?;
-            frndsMap.insert(frnd);
-        }
-
-        let mut rows = stmt.query(params![limit]).await?;
+        let mut rows = stmt
+            .query(params![
+                user,
+                limit + 1,
+                Cursor::key(cursor),
+                Cursor::id(cursor)
+            ])
+            .await?;
         while let Some(row) = rows.next().await? {
-            let username: String = row.get(2)?;
-            if !frndsMap.contains(&username) {
-                continue;
-            }
             let id: String = row.get(0)?;
             let user: String = row.get(1)?;
-            // let user_profile = row.get(3)?;
+            let username: String = row.get(2)?;
             let likes: u32 = row.get(3)?;
             let comments: u32 = row.get(4)?;
             let text: String = row.get(5)?;
-            let created_at: String = row.get(6)?;
+            let public: bool = row.get(6)?;
+            let created_at: String = row.get(7)?;
+            let edited_at: Option<String> = row.get::<Option<String>>(8)?;
+            let liked_by_me: bool = row.get(9)?;
 
             posts.push(RetrieveFriendsPost {
                 id,
@@ -305,11 +586,21 @@ impl RetrieveFriendsPost {
                 likes,
                 comments,
                 text,
-                // images,
+                public,
+                images: vec![],
                 created_at,
+                edited_at,
+                liked_by_me,
             });
         }
 
-        Ok(posts)
+        let mut page = Page::from_rows(posts, limit, |p| Cursor::new(&p.created_at, &p.id));
+        let ids: Vec<&str> = page.items.iter().map(|p| p.id.as_str()).collect();
+        let mut images = PostImages::retrieve_from_db(&ids, conn).await?;
+        for post in page.items.iter_mut() {
+            post.images = images.remove(&post.id).unwrap_or_default();
+        }
+
+        Ok(page)
     }
 }
@@ -1,26 +1,28 @@
-use std::sync::Arc;
-
 use libsql::{params, Connection};
 use serde::{Deserialize, Serialize};
 
+use super::cursor::{Cursor, Page};
+use super::institution::Scope;
+use crate::email::templates::Locale;
+use crate::error::AppError;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UpdateProfile {
     pub bio: Option<String>,
     pub first_name: Option<String>,
     pub last_name: Option<String>,
+    /// The language of the emails the user gets.
+    pub locale: Option<Locale>,
     // pub image: Option<String>,
 }
 
 impl UpdateProfile {
-    pub async fn update_into_db(
-        &self,
-        conn: &Connection,
-        user: &String,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn update_into_db(&self, conn: &Connection, user: &str) -> Result<(), AppError> {
         if self.bio.is_none()
             // && self.image.is_none()
             && self.first_name.is_none()
             && self.last_name.is_none()
+            && self.locale.is_none()
         {
             return Ok(());
         }
@@ -29,8 +31,8 @@ impl UpdateProfile {
         let mut params_vec = Vec::new();
 
         if let Some(bio) = &self.bio {
-            query.push_str("bio = ?1, ");
             params_vec.push(bio.clone());
+            query.push_str(&format!("bio = ?{}, ", params_vec.len()));
         }
 
         // if let Some(image) = &self.image {
@@ -39,24 +41,53 @@ impl UpdateProfile {
         // }
 
         if let Some(first_name) = &self.first_name {
-            query.push_str("first_name = ?2, ");
             params_vec.push(first_name.clone());
+            query.push_str(&format!("first_name = ?{}, ", params_vec.len()));
         }
 
         if let Some(last_name) = &self.last_name {
-            query.push_str("last_name = ?3, ");
             params_vec.push(last_name.clone());
+            query.push_str(&format!("last_name = ?{}, ", params_vec.len()));
+        }
+
+        if let Some(locale) = &self.locale {
+            params_vec.push(locale.as_str().to_string());
+            query.push_str(&format!("locale = ?{}, ", params_vec.len()));
         }
 
         query = query.trim_end_matches(", ").to_string(); // Remove trailing comma
-        query.push_str(" WHERE id = ?4");
         params_vec.push(user.to_string());
+        query.push_str(&format!(" WHERE id = ?{}", params_vec.len()));
 
         // Execute the query
         conn.execute(&query, params_vec).await?;
 
         Ok(())
     }
+
+    /// Returns the URL of the avatar this one replaced, if any.
+    pub async fn update_avatar_into_db(
+        conn: &Connection,
+        user: &str,
+        profile_url: &str,
+    ) -> Result<Option<String>, AppError> {
+        let mut rows = conn
+            .query("SELECT profile_url FROM users WHERE id = ?1", params![user])
+            .await?;
+        let previous = match rows.next().await? {
+            Some(row) => row.get::<Option<String>>(0)?,
+            None => None,
+        };
+        drop(rows);
+
+        conn.execute(
+            "UPDATE users SET profile_url = ?1 WHERE id = ?2",
+            params![profile_url, user],
+        )
+        .await?;
+
+        Ok(previous)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -66,27 +97,62 @@ pub struct RetrieveProfile {
     last_name: String,
     bio: Option<String>,
     username: String,
+    profile_url: Option<String>,
     posts: u32,
     // followers: u32,
     // following: u32
 }
 
 impl RetrieveProfile {
+    pub async fn exists(id: &str, conn: &Connection) -> Result<bool, AppError> {
+        let mut rows = conn
+            .query(
+                r#"
+            SELECT 1 FROM users
+            WHERE id = ?1 AND is_active = TRUE AND deactivated_at IS NULL
+            "#,
+                params![id],
+            )
+            .await?;
+
+        Ok(rows.next().await?.is_some())
+    }
+
+    /// Active users whose username contains `query`, on the campus of `user`
+    /// or on every campus.
     pub async fn get_from_db(
-        query: &String,
-        conn: &Arc<Connection>,
-    ) -> Result<Vec<RetrieveProfile>, Box<dyn std::error::Error>> {
+        query: &str,
+        user: &str,
+        scope: Scope,
+        conn: &Connection,
+        limit: i32,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<RetrieveProfile>, AppError> {
         let q = format!("%{}%", query.to_lowercase());
         let mut sql = conn
             .prepare(
                 r#"
-                SELECT id, first_name, last_name, bio, username, posts FROM users WHERE LOWER(username) LIKE ?1 AND is_active = TRUE;
+                SELECT id, first_name, last_name, bio, username, profile_url, posts FROM users
+                WHERE LOWER(username) LIKE ?1 AND is_active = TRUE AND deactivated_at IS NULL
+                AND (?6 OR institution_id IS (SELECT institution_id FROM users WHERE id = ?5))
+                AND (?3 IS NULL OR (username, id) > (?3, ?4))
+                ORDER BY username, id
+                LIMIT ?2;
             "#,
             )
             .await?;
 
         let mut profiles = vec![];
-        let mut rows = sql.query(params![q]).await?;
+        let mut rows = sql
+            .query(params![
+                q,
+                limit + 1,
+                Cursor::key(cursor),
+                Cursor::id(cursor),
+                user,
+                scope.cross_campus()
+            ])
+            .await?;
 
         while let Some(row) = rows.next().await? {
             log::info!("Row: {:?}", row);
@@ -95,7 +161,8 @@ impl RetrieveProfile {
             let last_name: String = row.get(2)?;
             let bio: Option<String> = row.get::<Option<String>>(3)?;
             let username: String = row.get(4)?;
-            let posts: u32 = row.get(5)?;
+            let profile_url: Option<String> = row.get::<Option<String>>(5)?;
+            let posts: u32 = row.get(6)?;
 
             profiles.push(RetrieveProfile {
                 id,
@@ -103,10 +170,13 @@ impl RetrieveProfile {
                 last_name,
                 bio,
                 username,
+                profile_url,
                 posts,
             });
         }
 
-        Ok(profiles)
+        Ok(Page::from_rows(profiles, limit, |p| {
+            Cursor::new(&p.username, &p.id)
+        }))
     }
 }
@@ -0,0 +1,169 @@
+use chrono::Utc;
+use libsql::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+pub struct CreateSession<'a> {
+    pub id: &'a str,
+    pub user: &'a str,
+    /// `jti` of the session's first refresh token.
+    pub jti: &'a str,
+    /// Unix time the first refresh token expires.
+    pub expires_at: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl CreateSession<'_> {
+    pub async fn insert_into_db(&self, conn: &Connection) -> Result<(), AppError> {
+        conn.execute(
+            r#"
+            INSERT INTO sessions (id, user, current_jti, expires_at, user_agent, ip)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+            params![
+                self.id,
+                self.user,
+                self.jti,
+                self.expires_at,
+                self.user_agent.clone(),
+                self.ip.clone()
+            ],
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Outcome of exchanging a refresh token.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Rotated,
+    /// The session was revoked, has expired or never existed.
+    Inactive,
+    /// The token had already been exchanged, so it was most likely stolen.
+    /// The whole session has been revoked.
+    Reused,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: String,
+    pub last_used_at: String,
+}
+
+impl Session {
+    /// Replaces the session's current refresh token `jti` with `new_jti`.
+    pub async fn rotate(
+        id: &str,
+        user: &str,
+        jti: &str,
+        new_jti: &str,
+        expires_at: i64,
+        conn: &Connection,
+    ) -> Result<Rotation, AppError> {
+        let now = Utc::now().timestamp();
+        let rotated = conn
+            .execute(
+                r#"
+            UPDATE sessions
+            SET current_jti = ?1, expires_at = ?2, last_used_at = CURRENT_TIMESTAMP
+            WHERE id = ?3 AND user = ?4 AND revoked_at IS NULL AND expires_at > ?5
+            AND current_jti = ?6
+            "#,
+                params![new_jti, expires_at, id, user, now, jti],
+            )
+            .await?;
+        if rotated > 0 {
+            return Ok(Rotation::Rotated);
+        }
+
+        // Still active but on a later token, so `jti` is being reused.
+        let revoked = conn
+            .execute(
+                r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = ?1 AND user = ?2 AND revoked_at IS NULL AND expires_at > ?3
+            "#,
+                params![id, user, now],
+            )
+            .await?;
+
+        Ok(if revoked > 0 {
+            Rotation::Reused
+        } else {
+            Rotation::Inactive
+        })
+    }
+
+    /// Active sessions of `user`, most recently used first.
+    pub async fn retrieve_from_db(user: &str, conn: &Connection) -> Result<Vec<Session>, AppError> {
+        let mut rows = conn
+            .query(
+                r#"
+            SELECT id, user_agent, ip, created_at, last_used_at
+            FROM sessions
+            WHERE user = ?1 AND revoked_at IS NULL AND expires_at > ?2
+            ORDER BY last_used_at DESC, id
+            "#,
+                params![user, Utc::now().timestamp()],
+            )
+            .await?;
+
+        let mut sessions = vec![];
+        while let Some(row) = rows.next().await? {
+            sessions.push(Session {
+                id: row.get(0)?,
+                user_agent: row.get::<Option<String>>(1)?,
+                ip: row.get::<Option<String>>(2)?,
+                created_at: row.get(3)?,
+                last_used_at: row.get(4)?,
+            });
+        }
+
+        Ok(sessions)
+    }
+
+    /// Returns `false` when `user` has no active session `id`.
+    pub async fn revoke(id: &str, user: &str, conn: &Connection) -> Result<bool, AppError> {
+        let revoked = conn
+            .execute(
+                r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = ?1 AND user = ?2 AND revoked_at IS NULL
+            "#,
+                params![id, user],
+            )
+            .await?;
+
+        Ok(revoked > 0)
+    }
+
+    /// Revokes every session of `user` except `keep`. Returns how many were
+    /// revoked.
+    pub async fn revoke_all(
+        user: &str,
+        keep: Option<&str>,
+        conn: &Connection,
+    ) -> Result<u64, AppError> {
+        let revoked = conn
+            .execute(
+                r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user = ?1 AND revoked_at IS NULL AND id IS NOT ?2
+            "#,
+                params![user, keep],
+            )
+            .await?;
+
+        Ok(revoked)
+    }
+}
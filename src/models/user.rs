@@ -1,11 +1,15 @@
-use std::sync::Arc;
-
-use chrono::NaiveDate;
-use libsql::{params, Connection, Row};
+use chrono::{NaiveDate, Utc};
+use libsql::{params, Connection};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use validator_derive::Validate;
 
+use super::cursor::{Cursor, Page};
+use crate::auth::role::Role;
+use crate::email::templates::Locale;
+use crate::error::AppError;
+
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Validate, Deserialize)]
 pub struct User {
     pub id: Uuid,
@@ -28,6 +32,211 @@ pub struct User {
     pub profile_url: Option<String>,
 }
 
+impl User {
+    /// The role of `id`, or `None` if there is no such account or it has been
+    /// deactivated.
+    pub async fn role_of(id: &str, conn: &Connection) -> Result<Option<Role>, AppError> {
+        let mut rows = conn
+            .query(
+                "SELECT role FROM users WHERE id = ?1 AND deactivated_at IS NULL",
+                params![id],
+            )
+            .await?;
+
+        match rows.next().await? {
+            Some(row) => Ok(Some(Role::try_from(row.get::<String>(0)?.as_str())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Changes the role of `id`. Access tokens issued before the change stop
+    /// working, so the user picks up the new role on the next refresh.
+    /// Returns `false` if there is no such account.
+    pub async fn set_role(id: &str, role: Role, conn: &Connection) -> Result<bool, AppError> {
+        let updated = conn
+            .execute(
+                r#"
+            UPDATE users
+            SET role = ?1, is_superuser = ?2, tokens_valid_after = ?3
+            WHERE id = ?4
+            "#,
+                params![
+                    role.as_str(),
+                    role == Role::Admin,
+                    Utc::now().timestamp_millis(),
+                    id
+                ],
+            )
+            .await?;
+
+        Ok(updated > 0)
+    }
+
+    /// Blocks `id` from logging in and revokes all of its sessions. Returns
+    /// `false` if there is no such account or it was already deactivated.
+    pub async fn deactivate(id: &str, conn: &Connection) -> Result<bool, AppError> {
+        let tran = conn.transaction().await?;
+        let updated = tran
+            .execute(
+                r#"
+            UPDATE users
+            SET deactivated_at = CURRENT_TIMESTAMP, tokens_valid_after = ?1
+            WHERE id = ?2 AND deactivated_at IS NULL
+            "#,
+                params![Utc::now().timestamp_millis(), id],
+            )
+            .await?;
+
+        tran.execute(
+            r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user = ?1 AND revoked_at IS NULL AND ?2 > 0
+            "#,
+            params![id, updated],
+        )
+        .await?;
+
+        tran.commit().await?;
+        Ok(updated > 0)
+    }
+
+    /// Replaces the password of the account registered with `email` and
+    /// revokes every session and token issued to it so far. Returns the
+    /// account's id, or `None` if there is no such account.
+    pub async fn reset_password(
+        email: &str,
+        password: &str,
+        conn: &Connection,
+    ) -> Result<Option<String>, AppError> {
+        let password = bcrypt::hash(password, bcrypt::DEFAULT_COST)?;
+
+        let tran = conn.transaction().await?;
+        let mut rows = tran
+            .query(
+                r#"
+            UPDATE users
+            SET password = ?1, tokens_valid_after = ?2
+            WHERE email = ?3
+            RETURNING id
+            "#,
+                params![password, Utc::now().timestamp_millis(), email],
+            )
+            .await?;
+
+        let id: Option<String> = match rows.next().await? {
+            Some(row) => Some(row.get(0)?),
+            None => None,
+        };
+        drop(rows);
+
+        tran.execute(
+            r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user = ?1 AND revoked_at IS NULL
+            "#,
+            params![id.clone()],
+        )
+        .await?;
+
+        tran.commit().await?;
+        Ok(id)
+    }
+
+    /// Deletes accounts that were never verified and are older than
+    /// `max_age_days`, together with their pending OTPs. Returns how many
+    /// accounts were removed.
+    ///
+    /// Accounts that posted, followed, liked or commented before login
+    /// required verification are left alone so no counters or content are
+    /// orphaned.
+    pub async fn purge_unverified(max_age_days: i64, conn: &Connection) -> Result<u64, AppError> {
+        const UNVERIFIED: &str = r#"
+            SELECT id FROM users
+            WHERE is_active = FALSE
+            AND posts = 0 AND followers = 0 AND following = 0
+            AND NOT EXISTS (SELECT 1 FROM post_likes WHERE post_likes.user = users.id)
+            AND NOT EXISTS (SELECT 1 FROM post_comments WHERE post_comments.user = users.id)
+            AND created_at < datetime('now', ?1)
+        "#;
+        let cutoff = format!("-{} days", max_age_days);
+
+        let tran = conn.transaction().await?;
+        tran.execute(
+            &format!(
+                "DELETE FROM otps WHERE email IN (SELECT email FROM users WHERE id IN ({}))",
+                UNVERIFIED
+            ),
+            params![cutoff.clone()],
+        )
+        .await?;
+        let deleted = tran
+            .execute(
+                &format!("DELETE FROM users WHERE id IN ({})", UNVERIFIED),
+                params![cutoff],
+            )
+            .await?;
+        tran.commit().await?;
+
+        Ok(deleted)
+    }
+}
+
+/// Who an email goes to and how to address them.
+#[derive(Debug, Clone)]
+pub struct Recipient {
+    pub email: String,
+    pub first_name: Option<String>,
+    pub locale: Locale,
+}
+
+impl Recipient {
+    /// The account registered with `email`. Addresses without an account are
+    /// written to in the default locale without a name.
+    pub async fn by_email(email: &str, conn: &Connection) -> Result<Recipient, AppError> {
+        let mut rows = conn
+            .query(
+                "SELECT email, first_name, locale FROM users WHERE email = ?1",
+                params![email],
+            )
+            .await?;
+
+        match rows.next().await? {
+            Some(row) => Recipient::from_row(&row),
+            None => Ok(Recipient {
+                email: email.to_string(),
+                first_name: None,
+                locale: Locale::default(),
+            }),
+        }
+    }
+
+    pub async fn by_id(id: &str, conn: &Connection) -> Result<Option<Recipient>, AppError> {
+        let mut rows = conn
+            .query(
+                "SELECT email, first_name, locale FROM users WHERE id = ?1",
+                params![id],
+            )
+            .await?;
+
+        match rows.next().await? {
+            Some(row) => Ok(Some(Recipient::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn from_row(row: &libsql::Row) -> Result<Recipient, AppError> {
+        Ok(Recipient {
+            email: row.get(0)?,
+            first_name: row
+                .get::<Option<String>>(1)?
+                .filter(|name| !name.is_empty()),
+            locale: Locale::from_tag(&row.get::<String>(2)?).unwrap_or_default(),
+        })
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CreateUser {
     #[validate(email(message = "Invalid email"))]
@@ -46,22 +255,25 @@ impl CreateUser {
     pub async fn insert_into_db(
         &self,
         uuid: Uuid,
-        conn: Arc<Connection>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+        institution: &str,
+        locale: Locale,
+        conn: &Connection,
+    ) -> Result<(), AppError> {
         let email = self.email.clone();
         let password = bcrypt::hash(self.password.clone(), bcrypt::DEFAULT_COST)?;
         let username = self.username.clone();
         let first_name = self.first_name.clone();
         let last_name = self.last_name.clone();
         let roll = self.roll.clone();
-        let dob = self.dob.clone();
+        let dob = self.dob;
 
         conn.execute(
             r#"
             INSERT INTO users (
-                id, email, password, username, first_name, last_name, roll, dob
+                id, email, password, username, first_name, last_name, roll, dob, institution_id,
+                locale
             ) VALUES (
-                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10
             )
             "#,
             params!(
@@ -72,7 +284,9 @@ impl CreateUser {
                 first_name,
                 last_name,
                 roll,
-                dob.to_string()
+                dob.to_string(),
+                institution,
+                locale.as_str()
             ),
         )
         .await?;
@@ -80,3 +294,63 @@ impl CreateUser {
         Ok(())
     }
 }
+
+/// An account as administrators see it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserSummary {
+    pub id: String,
+    pub username: String,
+    pub email: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub roll: String,
+    pub role: Role,
+    pub verified: bool,
+    pub deactivated_at: Option<String>,
+    pub institution_id: Option<String>,
+    pub created_at: String,
+}
+
+impl UserSummary {
+    /// Every account, ordered by username.
+    pub async fn retrieve_from_db(
+        conn: &Connection,
+        limit: i32,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<UserSummary>, AppError> {
+        let mut rows = conn
+            .query(
+                r#"
+            SELECT id, username, email, first_name, last_name, roll, role,
+                is_active, deactivated_at, institution_id, created_at
+            FROM users
+            WHERE ?2 IS NULL OR (username, id) > (?2, ?3)
+            ORDER BY username, id
+            LIMIT ?1
+            "#,
+                params![limit + 1, Cursor::key(cursor), Cursor::id(cursor)],
+            )
+            .await?;
+
+        let mut users = vec![];
+        while let Some(row) = rows.next().await? {
+            users.push(UserSummary {
+                id: row.get(0)?,
+                username: row.get(1)?,
+                email: row.get(2)?,
+                first_name: row.get(3)?,
+                last_name: row.get(4)?,
+                roll: row.get(5)?,
+                role: Role::try_from(row.get::<String>(6)?.as_str())?,
+                verified: row.get::<Option<bool>>(7)?.unwrap_or(false),
+                deactivated_at: row.get::<Option<String>>(8)?,
+                institution_id: row.get::<Option<String>>(9)?,
+                created_at: row.get(10)?,
+            });
+        }
+
+        Ok(Page::from_rows(users, limit, |u| {
+            Cursor::new(&u.username, &u.id)
+        }))
+    }
+}
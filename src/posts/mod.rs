@@ -1,12 +1,9 @@
 use std::sync::Arc;
 
 use actix_web::{
-    error,
     web::{Data, Json, Path, Query},
     HttpMessage, HttpRequest, HttpResponse,
 };
-use libsql::Connection;
-use log::error;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use uuid::{Timestamp, Uuid};
@@ -14,131 +11,148 @@ use validator::Validate;
 
 use crate::{
     auth::token::Claims,
-    // aws::S3,
-    models::comment::{CreateComment, RetrieveComment},
-    models::post::{self, CreatePost, CreatePostImage, LikePost},
+    db::Conn,
+    error::AppError,
+    models::comment::{
+        CommentAccess, CreateComment, DeleteComment, EditComment, NewComment, RetrieveComment,
+    },
+    models::cursor::{page_size, Cursor},
+    models::institution::Scope,
+    models::post::{
+        self, CreatePost, CreatePostImage, DeletePost, EditPost, LikeChange, LikePost, Ownership,
+        RetrieveLike,
+    },
+    storage::{image_extension, Storage},
 };
 
-// #[actix_web::post("/create")]
-// pub async fn create(
-//     s3: Data<S3>,
-//     req: HttpRequest,
-//     post: Json<CreatePost>,
-//     conn: Data<Connection>,
-// ) -> Result<HttpResponse, actix_web::Error> {
-//     post.validate().map_err(|e| {
-//         error!("Validation error: {}", post.validate().unwrap_err());
-//         error::ErrorBadRequest(serde_json::to_string(&e).unwrap_or_default())
-//     })?;
+#[actix_web::post("/create")]
+pub async fn create(
+    storage: Data<dyn Storage>,
+    req: HttpRequest,
+    post: Json<CreatePost>,
+    conn: Conn,
+) -> Result<HttpResponse, AppError> {
+    post.validate()?;
 
-//     let post = post.into_inner();
+    let post = post.into_inner();
 
-//     let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
-//     let context = uuid::Context::new(rand::random());
-//     let ts = Timestamp::from_unix(&context, 1497624119, 1234);
-//     let post_id = uuid::Uuid::new_v6(ts, &[1, 2, 3, 4, 5, 6]);
+    let mut extensions = vec![];
+    for img in &post.images {
+        match image_extension(&img.content_type) {
+            Some(ext) => extensions.push(ext),
+            None => {
+                return Err(AppError::BadRequest(
+                    "invalid_content_type",
+                    "Invalid image content type".to_string(),
+                ))
+            }
+        }
+    }
 
-//     let conn = conn.into_inner();
-//     let tran = conn.transaction().await.map_err(|e| {
-//         error!("Error: Unable to create transaction for post {}", e);
-//         error::ErrorBadGateway("Some went wrong. Try again later")
-//     })?;
+    let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+    let context = uuid::Context::new(rand::random());
+    let ts = Timestamp::from_unix(&context, 1497624119, 1234);
+    let post_id = uuid::Uuid::new_v6(ts, &[1, 2, 3, 4, 5, 6]).to_string();
 
-//     post.insert_into_db(&user.sub, &post_id.to_string(), &tran)
-//         .await
-//         .map_err(|e| {
-//             error!("Error creating Post {}", e);
-//             error::ErrorBadGateway("Unable to upload post data")
-//         })?;
+    let conn = conn.into_inner();
+    let tran = conn.transaction().await?;
+
+    post.insert_into_db(&user.sub, &post_id, &tran).await?;
+
+    let mut images_res = vec![];
+    for (img, ext) in post.images.iter().zip(extensions) {
+        let image_id = Uuid::new_v4().to_string();
+        let image_key = format!("posts/{}/{}/{}.{}", user.sub, post_id, image_id, ext);
+        let upload_url = storage
+            .upload_url(&image_key, &img.content_type)
+            .await
+            .map_err(AppError::internal)?;
+        let post_image = CreatePostImage {
+            image: storage.public_url(&image_key),
+        };
+
+        post_image
+            .insert_into_db(&image_id, &post_id, &tran)
+            .await?;
+
+        images_res.push(json!({
+            "id": image_id,
+            "url": post_image,
+            "upload_url": upload_url,
+            "content_type": img.content_type,
+        }));
+    }
+
+    tran.commit().await?;
+
+    Ok(HttpResponse::Created().json(json!({ "id": post_id, "images": images_res })))
+}
 
-//     let mut images_res: Vec<CreatePostImage> = vec![];
-//     for img in post.images {
-//         let mut image_type = "jpg".to_string();
-//         if let Some(image) = img.image.split(".").collect::<Vec<&str>>().last() {
-//             let image = image.to_string();
-//             if image == "jpg" || image == "png" || image == "jpeg" {
-//                 image_type = image;
-//             } else {
-//                 Err(error::ErrorBadRequest("Invalid file format"))?
-//             }
-//         } else {
-//             Err(error::ErrorBadRequest(
-//                 "File name do not have . or any type",
-//             ))?
-//         }
-
-//         let image_id = Uuid::new_v4().to_string();
-//         let image_key = format!("posts/{}/{}.{}", user.sub, post_id, image_type);
-//         let url = s3.presigned_url(image_key.clone()).await.map_err(|e| {
-//             error!("Error Generating presigned url {}", e);
-//             error::ErrorBadGateway("Something went wrong while upload")
-//         })?;
-//         let post_image = CreatePostImage {
-//             image: format!(
-//                 "https://s3.ap-south-1.amazonaws.com/{}/{}",
-//                 s3.bucket, image_key
-//             ),
-//         };
-
-//         post_image
-//             .insert_into_db(&image_id, &post_id.to_string(), &tran)
-//             .await
-//             .map_err(|e| {
-//                 error!("Error while inserting post image data {}", e);
-//                 error::ErrorBadGateway("Something went wrong while upload")
-//             })?;
-
-//         images_res.push(post_image);
-//     }
-
-//     tran.commit().await.map_err(|e| {
-//         error!("Transaction of post failed at last point {}", e);
-//         error::ErrorBadGateway("Something unexpected happened")
-//     })?;
-
-//     Ok(HttpResponse::Created().json(json!({ "images": images_res })))
-// }
+// ==================================================== EDIT POST ======================================================
 
-#[actix_web::post("/create")]
-pub async fn create(
-    // s3: Data<S3>,
+#[actix_web::patch("/{post_id}")]
+pub async fn edit(
     req: HttpRequest,
-    post: Json<CreatePost>,
-    conn: Data<Connection>,
-) -> Result<HttpResponse, actix_web::Error> {
-    post.validate().map_err(|e| {
-        error!("Validation error: {}", post.validate().unwrap_err());
-        error::ErrorBadRequest(serde_json::to_string(&e).unwrap_or_default())
-    })?;
+    conn: Conn,
+    post_id: Path<String>,
+    post: Json<EditPost>,
+) -> Result<HttpResponse, AppError> {
+    post.validate()?;
 
     let post = post.into_inner();
+    if post.text.is_none() && post.public.is_none() {
+        return Err(AppError::BadRequest(
+            "empty_update",
+            "Nothing to update".to_string(),
+        ));
+    }
 
     let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
-    let context = uuid::Context::new(rand::random());
-    let ts = Timestamp::from_unix(&context, 1497624119, 1234);
-    let post_id = uuid::Uuid::new_v6(ts, &[1, 2, 3, 4, 5, 6]);
 
     let conn = conn.into_inner();
+    let ownership = post.update_into_db(&user.sub, &post_id, &conn).await?;
+
+    match ownership {
+        Ownership::Owner => Ok(HttpResponse::Ok().body("Post updated")),
+        Ownership::NotOwner => Err(AppError::Forbidden(
+            "not_owner",
+            "You can only edit your own posts".to_string(),
+        )),
+        Ownership::NotFound => Err(AppError::not_found("Post not found")),
+    }
+}
 
-    post.insert_into_db(&user.sub, &post_id.to_string(), &conn)
-        .await
-        .map_err(|e| {
-            error!("Error creating Post {}", e);
-            error::ErrorBadGateway("Unable to upload post data")
-        })?;
+// ==================================================== DELETE POST ======================================================
 
-    Ok(HttpResponse::Created().body("Post created"))
+#[actix_web::delete("/{post_id}")]
+pub async fn delete(
+    req: HttpRequest,
+    conn: Conn,
+    post_id: Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+
+    let conn = conn.into_inner();
+    let ownership = DeletePost::delete_from_db(&user.sub, &post_id, &conn).await?;
+
+    match ownership {
+        Ownership::Owner => Ok(HttpResponse::NoContent().finish()),
+        Ownership::NotOwner => Err(AppError::Forbidden(
+            "not_owner",
+            "You can only delete your own posts".to_string(),
+        )),
+        Ownership::NotFound => Err(AppError::not_found("Post not found")),
+    }
 }
 
 // ==================================================== LIST POSTS FOR THE MAIN PAGE ======================================================
 
 // #[actix_web::get("/list")]
 // pub async fn list_posts(
-//     s3: Data<S3>,
 //     req: HttpRequest,
-//     conn: Data<Connection>,
+//     conn: Conn,
 //     query: Query<Option<u32>>,
-// ) -> Result<HttpResponse, actix_web::Error> {
+// ) -> Result<HttpResponse, AppError> {
 //     let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
 
 //     let limit = query.unwrap_or(10);
@@ -159,28 +173,40 @@ pub async fn create(
 #[derive(Debug, Serialize, Deserialize)]
 struct CountQuery {
     count: Option<i32>,
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FeedQuery {
+    count: Option<i32>,
+    cursor: Option<String>,
+    /// `all` includes posts from other campuses.
+    #[serde(default)]
+    scope: Scope,
 }
 
 #[actix_web::get("/list")]
 pub async fn list_other_posts(
-    // s3: Data<S3>,
     req: HttpRequest,
-    conn: Data<Connection>,
-    query: Query<CountQuery>,
-) -> Result<HttpResponse, actix_web::Error> {
+    conn: Conn,
+    query: Query<FeedQuery>,
+) -> Result<HttpResponse, AppError> {
     let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
     let query = query.into_inner();
     log::info!("Query: {:?}", query);
 
-    let limit = query.count.unwrap_or(10);
+    let limit = page_size(query.count);
+    let cursor = Cursor::from_query(query.cursor.as_deref())?;
 
     let conn = conn.into_inner();
-    let posts = post::RetrieveOtherPost::retrieve_from_db(&user.sub, &conn, limit)
-        .await
-        .map_err(|e| {
-            error!("Error while retrieving posts {}", e);
-            error::ErrorBadGateway("Something went wrong while fetching posts")
-        })?;
+    let posts = post::RetrieveOtherPost::retrieve_from_db(
+        &user.sub,
+        query.scope,
+        &conn,
+        limit,
+        cursor.as_ref(),
+    )
+    .await?;
 
     Ok(HttpResponse::Ok().json(json!(posts)))
 }
@@ -189,24 +215,21 @@ pub async fn list_other_posts(
 
 #[actix_web::get("/list/friends")]
 pub async fn list_friends_posts(
-    // s3: Data<S3>,
     req: HttpRequest,
-    conn: Data<Connection>,
+    conn: Conn,
     query: Query<CountQuery>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, AppError> {
     let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
     let query = query.into_inner();
     log::info!("Query: {:?}", query);
 
-    let limit = query.count.unwrap_or(10);
+    let limit = page_size(query.count);
+    let cursor = Cursor::from_query(query.cursor.as_deref())?;
 
     let conn = conn.into_inner();
-    let posts = post::RetrieveOtherPost::retrieve_from_db(&user.sub, &conn, limit)
-        .await
-        .map_err(|e| {
-            error!("Error while retrieving posts {}", e);
-            error::ErrorBadGateway("Something went wrong while fetching posts")
-        })?;
+    let posts =
+        post::RetrieveFriendsPost::retrieve_from_db(&user.sub, &conn, limit, cursor.as_ref())
+            .await?;
 
     Ok(HttpResponse::Ok().json(json!(posts)))
 }
@@ -216,20 +239,55 @@ pub async fn list_friends_posts(
 #[actix_web::post("/like/{post_id}")]
 pub async fn like(
     req: HttpRequest,
-    conn: Data<Connection>,
+    conn: Conn,
+    post_id: Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+
+    let conn = conn.into_inner();
+    let change = LikePost::insert_into_db(&user.sub, &post_id, &conn).await?;
+
+    match change {
+        LikeChange::PostNotFound => Err(AppError::not_found("Post not found")),
+        _ => Ok(HttpResponse::Ok().body("Post liked")),
+    }
+}
+
+// ==================================================== UNLIKE POST ======================================================
+
+#[actix_web::delete("/like/{post_id}")]
+pub async fn unlike(
+    req: HttpRequest,
+    conn: Conn,
     post_id: Path<String>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, AppError> {
     let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
 
     let conn = conn.into_inner();
-    LikePost::insert_into_db(&user.sub, &post_id, &conn)
-        .await
-        .map_err(|e| {
-            error!("Error while liking post {}", e);
-            error::ErrorBadGateway("Something went wrong while liking post")
-        })?;
-
-    Ok(HttpResponse::Ok().body("Post liked"))
+    let change = LikePost::delete_from_db(&user.sub, &post_id, &conn).await?;
+
+    match change {
+        LikeChange::PostNotFound => Err(AppError::not_found("Post not found")),
+        _ => Ok(HttpResponse::Ok().body("Post unliked")),
+    }
+}
+
+// ==================================================== LIST LIKES ======================================================
+
+#[actix_web::get("/{post_id}/likes")]
+pub async fn list_likes(
+    conn: Conn,
+    post_id: Path<String>,
+    query: Query<CountQuery>,
+) -> Result<HttpResponse, AppError> {
+    let query = query.into_inner();
+    let limit = page_size(query.count);
+    let cursor = Cursor::from_query(query.cursor.as_deref())?;
+
+    let conn = conn.into_inner();
+    let likes = RetrieveLike::retrieve_from_db(&post_id, &conn, limit, cursor.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(json!(likes)))
 }
 
 // ==================================================== COMMENT ON POST ======================================================
@@ -237,28 +295,77 @@ pub async fn like(
 #[actix_web::post("/comment")]
 pub async fn comment(
     req: HttpRequest,
-    conn: Data<Connection>,
+    conn: Conn,
     comment: Json<CreateComment>,
-) -> Result<HttpResponse, actix_web::Error> {
-    comment.validate().map_err(|e| {
-        error!("Validation error: {}", comment.validate().unwrap_err());
-        error::ErrorBadRequest(serde_json::to_string(&e).unwrap_or_default())
-    })?;
+) -> Result<HttpResponse, AppError> {
+    comment.validate()?;
 
     let comment = comment.into_inner();
     let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
 
     let conn = conn.into_inner();
 
-    comment
-        .insert_into_db(&user.sub, &conn)
-        .await
-        .map_err(|e| {
-            error!("Error while commenting on post {}", e);
-            error::ErrorBadGateway("Something went wrong while commenting on post")
-        })?;
+    let created = comment.insert_into_db(&user.sub, &conn).await?;
+
+    match created {
+        NewComment::Created(id) => Ok(HttpResponse::Created().json(json!({ "id": id }))),
+        NewComment::PostNotFound => Err(AppError::not_found("Post not found")),
+        NewComment::ParentNotFound => Err(AppError::not_found("Comment not found")),
+        NewComment::TooDeep => Err(AppError::BadRequest(
+            "reply_too_deep",
+            "Cannot reply to a reply".to_string(),
+        )),
+    }
+}
+
+// ==================================================== EDIT COMMENT ======================================================
 
-    Ok(HttpResponse::Created().body("Commentd added"))
+#[actix_web::patch("/comment/{comment_id}")]
+pub async fn edit_comment(
+    req: HttpRequest,
+    conn: Conn,
+    comment_id: Path<String>,
+    form: Json<EditComment>,
+) -> Result<HttpResponse, AppError> {
+    form.validate()?;
+
+    let form = form.into_inner();
+    let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+
+    let conn = conn.into_inner();
+    let access = form.update_into_db(&user.sub, &comment_id, &conn).await?;
+
+    match access {
+        CommentAccess::Allowed => Ok(HttpResponse::Ok().body("Comment updated")),
+        CommentAccess::Forbidden => Err(AppError::Forbidden(
+            "not_owner",
+            "You can only edit your own comments".to_string(),
+        )),
+        CommentAccess::NotFound => Err(AppError::not_found("Comment not found")),
+    }
+}
+
+// ==================================================== DELETE COMMENT ======================================================
+
+#[actix_web::delete("/comment/{comment_id}")]
+pub async fn delete_comment(
+    req: HttpRequest,
+    conn: Conn,
+    comment_id: Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+
+    let conn = conn.into_inner();
+    let access = DeleteComment::delete_from_db(&user.sub, &comment_id, &conn).await?;
+
+    match access {
+        CommentAccess::Allowed => Ok(HttpResponse::NoContent().finish()),
+        CommentAccess::Forbidden => Err(AppError::Forbidden(
+            "not_owner",
+            "Only the comment author or the post owner can delete this comment".to_string(),
+        )),
+        CommentAccess::NotFound => Err(AppError::not_found("Comment not found")),
+    }
 }
 
 // ==================================================== RETRIEVE COMMENTS ======================================================
@@ -266,18 +373,37 @@ pub async fn comment(
 #[actix_web::get("/comments/{post_id}")]
 pub async fn list_comments(
     // req: HttpRequest,
-    conn: Data<Connection>,
+    conn: Conn,
     post_id: Path<String>,
-) -> Result<HttpResponse, actix_web::Error> {
+    query: Query<CountQuery>,
+) -> Result<HttpResponse, AppError> {
     // let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+    let query = query.into_inner();
+    let limit = page_size(query.count);
+    let cursor = Cursor::from_query(query.cursor.as_deref())?;
 
     let conn = conn.into_inner();
-    let comments = RetrieveComment::retrieve_from_db(&post_id, &conn)
-        .await
-        .map_err(|e| {
-            error!("Error while retrieving comments {}", e);
-            error::ErrorBadGateway("Something went wrong while fetching comments")
-        })?;
+    let comments =
+        RetrieveComment::retrieve_from_db(&post_id, &conn, limit, cursor.as_ref()).await?;
 
     Ok(HttpResponse::Ok().json(json!(comments)))
 }
+
+// ==================================================== RETRIEVE REPLIES ======================================================
+
+#[actix_web::get("/comment/{comment_id}/replies")]
+pub async fn list_replies(
+    conn: Conn,
+    comment_id: Path<String>,
+    query: Query<CountQuery>,
+) -> Result<HttpResponse, AppError> {
+    let query = query.into_inner();
+    let limit = page_size(query.count);
+    let cursor = Cursor::from_query(query.cursor.as_deref())?;
+
+    let conn = conn.into_inner();
+    let replies =
+        RetrieveComment::replies_from_db(&comment_id, &conn, limit, cursor.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(json!(replies)))
+}
@@ -0,0 +1,59 @@
+use std::io::Cursor;
+
+use image::{imageops::FilterType, ImageFormat};
+
+/// Largest avatar upload accepted, before resizing.
+pub const MAX_AVATAR_BYTES: usize = 5 * 1024 * 1024;
+
+/// Square sizes, in pixels, an avatar is stored at. The last one is used as
+/// `users.profile_url`.
+pub const AVATAR_SIZES: &[u32] = &[64, 256];
+
+/// Where one size of an avatar is stored. Every upload gets its own `upload`
+/// id so old URLs never show the new picture.
+pub fn avatar_key(user: &str, upload: &str, size: u32) -> String {
+    format!("avatars/{}/{}/{}.jpg", user, upload, size)
+}
+
+/// Every size of the avatar of `user` that `key` is one size of.
+pub fn avatar_keys(user: &str, key: &str) -> Option<Vec<String>> {
+    let (upload, _) = key
+        .strip_prefix(&format!("avatars/{}/", user))?
+        .split_once('/')?;
+    Some(
+        AVATAR_SIZES
+            .iter()
+            .map(|&size| avatar_key(user, upload, size))
+            .collect(),
+    )
+}
+
+pub fn avatar_format(content_type: &str) -> Option<ImageFormat> {
+    match content_type {
+        "image/jpeg" => Some(ImageFormat::Jpeg),
+        "image/png" => Some(ImageFormat::Png),
+        "image/webp" => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+/// Decodes an uploaded image and renders a center-cropped JPEG for every
+/// entry in `AVATAR_SIZES`.
+pub fn render_avatars(
+    data: &[u8],
+    format: ImageFormat,
+) -> Result<Vec<(u32, Vec<u8>)>, image::ImageError> {
+    let img = image::load_from_memory_with_format(data, format)?;
+
+    AVATAR_SIZES
+        .iter()
+        .map(|&size| {
+            let resized = img
+                .resize_to_fill(size, size, FilterType::Lanczos3)
+                .into_rgb8();
+            let mut out = Cursor::new(vec![]);
+            resized.write_to(&mut out, ImageFormat::Jpeg)?;
+            Ok((size, out.into_inner()))
+        })
+        .collect()
+}
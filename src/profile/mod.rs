@@ -1,61 +1,256 @@
 use std::sync::Arc;
 
-use actix_multipart::form::json;
+use actix_multipart::form::{bytes::Bytes, MultipartForm};
 use actix_web::{
-    error,
-    web::{Data, Form, Query},
+    web::{self, Data, Form, Path, Query},
     HttpMessage, HttpRequest, HttpResponse,
 };
-use libsql::Connection;
-use log::error;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 use crate::{
     auth::token::Claims,
+    db::Conn,
+    error::AppError,
+    models::cursor::{page_size, Cursor},
+    models::follow::{Follow, RetrieveFollow},
+    models::institution::{Institution, Scope},
     models::profile::{RetrieveProfile, UpdateProfile},
+    storage::Storage,
 };
 
+pub mod avatar;
+
 #[actix_web::post("/update")]
 pub async fn update(
     req: HttpRequest,
-    conn: Data<Connection>,
+    conn: Conn,
     form: Form<UpdateProfile>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, AppError> {
     let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
 
     let conn = conn.into_inner();
-    form.update_into_db(&conn, &user.sub).await.map_err(|e| {
-        error!("Error while updating profile {}", e);
-        error::ErrorBadRequest("Something went wrong while updating profile")
-    })?;
+    form.update_into_db(&conn, &user.sub).await?;
 
     Ok(HttpResponse::Ok().finish())
 }
 
+// ==================================================== PROFILE PICTURE ======================================================
+
+/// The size limit is `MAX_AVATAR_BYTES`, set on the scope's `MultipartFormConfig`.
+#[derive(Debug, MultipartForm)]
+pub struct AvatarUpload {
+    image: Bytes,
+}
+
+#[actix_web::post("/avatar")]
+pub async fn upload_avatar(
+    req: HttpRequest,
+    conn: Conn,
+    storage: Data<dyn Storage>,
+    form: MultipartForm<AvatarUpload>,
+) -> Result<HttpResponse, AppError> {
+    let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+    let image = form.into_inner().image;
+
+    let format = match image
+        .content_type
+        .as_ref()
+        .and_then(|ct| avatar::avatar_format(ct.essence_str()))
+    {
+        Some(format) => format,
+        None => {
+            return Err(AppError::UnsupportedMediaType(
+                "Profile picture must be a JPEG, PNG or WebP image".to_string(),
+            ))
+        }
+    };
+
+    let rendered = web::block(move || avatar::render_avatars(&image.data, format))
+        .await?
+        .map_err(|e| {
+            info!("Unable to decode profile picture {}", e);
+            AppError::BadRequest(
+                "invalid_image",
+                "Profile picture could not be decoded".to_string(),
+            )
+        })?;
+
+    let upload_id = uuid::Uuid::new_v4().to_string();
+    let mut thumbnails = serde_json::Map::new();
+    let mut profile_url = String::new();
+    for (size, bytes) in rendered {
+        let key = avatar::avatar_key(&user.sub, &upload_id, size);
+        storage
+            .put(&key, bytes, "image/jpeg")
+            .await
+            .map_err(AppError::internal)?;
+
+        profile_url = storage.public_url(&key);
+        thumbnails.insert(size.to_string(), json!(profile_url));
+    }
+
+    let conn = conn.into_inner();
+    let previous = UpdateProfile::update_avatar_into_db(&conn, &user.sub, &profile_url).await?;
+
+    // The old files are no longer used once the new avatar is saved.
+    let old_keys = previous
+        .and_then(|url| storage.key_for_url(&url))
+        .and_then(|key| avatar::avatar_keys(&user.sub, &key))
+        .unwrap_or_default();
+    for key in old_keys {
+        if let Err(e) = storage.delete(&key).await {
+            warn!("Unable to delete old profile picture {}: {}", key, e);
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "profile_url": profile_url,
+        "thumbnails": thumbnails,
+    })))
+}
+
+// ==================================================== SEARCH PROFILES ======================================================
+
 #[derive(Debug, Deserialize, Serialize)]
 struct SearchProfile {
     string: String,
+    count: Option<i32>,
+    cursor: Option<String>,
+    /// `all` searches every campus.
+    #[serde(default)]
+    scope: Scope,
 }
 
 #[actix_web::get("/search")]
 pub async fn search(
     req: HttpRequest,
-    conn: Data<Connection>,
+    conn: Conn,
     query: Query<SearchProfile>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, AppError> {
     let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
     let query = query.into_inner();
 
     log::info!("Query {:?}", query);
+    let limit = page_size(query.count);
+    let cursor = Cursor::from_query(query.cursor.as_deref())?;
 
     let conn = conn.into_inner();
-    let profiles = RetrieveProfile::get_from_db(&query.string.to_lowercase(), &conn)
-        .await
-        .map_err(|e| {
-            error!("Error while searching profiles {}", e);
-            error::ErrorBadGateway("Something went wrong while searching profiles")
-        })?;
+    let profiles = RetrieveProfile::get_from_db(
+        &query.string.to_lowercase(),
+        &user.sub,
+        query.scope,
+        &conn,
+        limit,
+        cursor.as_ref(),
+    )
+    .await?;
 
     Ok(HttpResponse::Ok().json(json!(profiles)))
 }
+
+// ==================================================== FOLLOW / UNFOLLOW ======================================================
+
+#[derive(Debug, Deserialize, Serialize)]
+struct FollowQuery {
+    /// `all` allows following someone on another campus.
+    #[serde(default)]
+    scope: Scope,
+}
+
+#[actix_web::post("/{id}/follow")]
+pub async fn follow(
+    req: HttpRequest,
+    conn: Conn,
+    id: Path<String>,
+    query: Query<FollowQuery>,
+) -> Result<HttpResponse, AppError> {
+    let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+    let id = id.into_inner();
+
+    if user.sub == id {
+        return Err(AppError::BadRequest(
+            "cannot_follow_self",
+            "You cannot follow yourself".to_string(),
+        ));
+    }
+
+    let conn = conn.into_inner();
+    if !RetrieveProfile::exists(&id, &conn).await? {
+        return Err(AppError::not_found("User not found"));
+    }
+
+    if !query.scope.cross_campus() && !Institution::same_campus(&user.sub, &id, &conn).await? {
+        return Err(AppError::Forbidden(
+            "different_campus",
+            "This user is on another campus. Use scope=all to follow them".to_string(),
+        ));
+    }
+
+    let followed = Follow::insert_into_db(&user.sub, &id, &conn).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "following": true, "changed": followed })))
+}
+
+#[actix_web::delete("/{id}/follow")]
+pub async fn unfollow(
+    req: HttpRequest,
+    conn: Conn,
+    id: Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let user = req.extensions().get::<Arc<Claims>>().unwrap().clone();
+    let id = id.into_inner();
+
+    if user.sub == id {
+        return Err(AppError::BadRequest(
+            "cannot_follow_self",
+            "You cannot unfollow yourself".to_string(),
+        ));
+    }
+
+    let conn = conn.into_inner();
+    let unfollowed = Follow::delete_from_db(&user.sub, &id, &conn).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "following": false, "changed": unfollowed })))
+}
+
+// ==================================================== FOLLOWERS / FOLLOWING ======================================================
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PageQuery {
+    count: Option<i32>,
+    cursor: Option<String>,
+}
+
+#[actix_web::get("/{id}/followers")]
+pub async fn list_followers(
+    conn: Conn,
+    id: Path<String>,
+    query: Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let query = query.into_inner();
+    let limit = page_size(query.count);
+    let cursor = Cursor::from_query(query.cursor.as_deref())?;
+
+    let conn = conn.into_inner();
+    let users = RetrieveFollow::followers_from_db(&id, &conn, limit, cursor.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(json!(users)))
+}
+
+#[actix_web::get("/{id}/following")]
+pub async fn list_following(
+    conn: Conn,
+    id: Path<String>,
+    query: Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let query = query.into_inner();
+    let limit = page_size(query.count);
+    let cursor = Cursor::from_query(query.cursor.as_deref())?;
+
+    let conn = conn.into_inner();
+    let users = RetrieveFollow::following_from_db(&id, &conn, limit, cursor.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(json!(users)))
+}
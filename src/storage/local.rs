@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    error::PayloadError,
+    web::{self, Bytes, Data, Query},
+    HttpRequest, HttpResponse,
+};
+use async_trait::async_trait;
+use log::error;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::Deserialize;
+
+use super::{Storage, UPLOAD_URL_TTL_SECS};
+use crate::error::AppError;
+
+pub const MAX_UPLOAD_BYTES: usize = 10 * 1024 * 1024;
+
+struct PendingUpload {
+    token: String,
+    content_type: String,
+    expires: Instant,
+}
+
+/// Stores media on the local filesystem. Files are served by actix under
+/// `/media` and uploaded with a one-time token through `PUT /uploads/{key}`,
+/// mimicking a presigned S3 URL.
+pub struct LocalStorage {
+    root: PathBuf,
+    base_url: String,
+    pending: Mutex<HashMap<String, PendingUpload>>,
+}
+
+impl LocalStorage {
+    pub fn init(root: impl Into<PathBuf>, base_url: String) -> std::io::Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+
+        Ok(Self {
+            root,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path_for(&self, key: &str) -> Option<PathBuf> {
+        let key = Path::new(key);
+        if key.components().all(|c| matches!(c, Component::Normal(_))) {
+            Some(self.root.join(key))
+        } else {
+            None
+        }
+    }
+
+    /// Consumes the upload token for `key` if it is valid for `content_type`.
+    fn take_pending(&self, key: &str, token: &str, content_type: &str) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, p| p.expires > Instant::now());
+
+        match pending.get(key) {
+            Some(p) if p.token == token && p.content_type == content_type => {
+                pending.remove(key);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub async fn write(&self, key: &str, bytes: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.path_for(key).ok_or("Invalid storage key")?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, bytes).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn upload_url(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        self.path_for(key).ok_or("Invalid storage key")?;
+
+        let token: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+
+        self.pending.lock().unwrap().insert(
+            key.to_string(),
+            PendingUpload {
+                token: token.clone(),
+                content_type: content_type.to_string(),
+                expires: Instant::now() + Duration::from_secs(UPLOAD_URL_TTL_SECS),
+            },
+        );
+
+        Ok(format!("{}/uploads/{}?token={}", self.base_url, key, token))
+    }
+
+    async fn put(
+        &self,
+        key: &str,
+        bytes: Vec<u8>,
+        _content_type: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write(key, &bytes).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.path_for(key).ok_or("Invalid storage key")?;
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        format!("{}/media/{}", self.base_url, key)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadQuery {
+    token: String,
+}
+
+/// Receives an upload for a URL previously issued by `LocalStorage::upload_url`.
+async fn upload(
+    req: HttpRequest,
+    storage: Data<LocalStorage>,
+    key: web::Path<String>,
+    query: Query<UploadQuery>,
+    body: Result<Bytes, actix_web::Error>,
+) -> Result<HttpResponse, AppError> {
+    let content_type = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    // Read errors are answered here rather than by the extractor so they get
+    // the usual error body.
+    let body = body.map_err(|e| match e.as_error::<PayloadError>() {
+        Some(PayloadError::Overflow) => {
+            AppError::PayloadTooLarge(format!("Uploads can be at most {} bytes", MAX_UPLOAD_BYTES))
+        }
+        _ => AppError::BadRequest("malformed_body", e.to_string()),
+    })?;
+
+    if !storage.take_pending(&key, &query.token, content_type) {
+        return Err(AppError::Forbidden(
+            "invalid_upload_url",
+            "Invalid or expired upload URL".to_string(),
+        ));
+    }
+
+    storage.write(&key, &body).await.map_err(|e| {
+        error!("Error while writing upload {}", e);
+        AppError::internal("Something went wrong while uploading")
+    })?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Routes for uploading to and serving from local storage.
+pub fn configure(storage: Data<LocalStorage>) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        let root = storage.root().to_path_buf();
+        cfg.service(
+            web::resource("/uploads/{key:.*}")
+                .app_data(storage)
+                .app_data(web::PayloadConfig::new(MAX_UPLOAD_BYTES))
+                .route(web::put().to(upload)),
+        )
+        .service(actix_files::Files::new("/media", root));
+    }
+}
@@ -0,0 +1,50 @@
+use async_trait::async_trait;
+
+pub mod local;
+pub mod s3;
+
+/// How long an upload URL handed to a client stays valid.
+pub const UPLOAD_URL_TTL_SECS: u64 = 3600;
+
+pub const ALLOWED_IMAGE_TYPES: &[(&str, &str)] = &[
+    ("image/jpeg", "jpg"),
+    ("image/png", "png"),
+    ("image/webp", "webp"),
+];
+
+/// Returns the file extension for an allowed image content type.
+pub fn image_extension(content_type: &str) -> Option<&'static str> {
+    ALLOWED_IMAGE_TYPES
+        .iter()
+        .find(|(ct, _)| ct.eq_ignore_ascii_case(content_type))
+        .map(|(_, ext)| *ext)
+}
+
+/// Object storage for user uploaded media. Clients either upload directly to
+/// the URL returned by `upload_url`, or the server writes the object itself
+/// with `put`; in both cases only `public_url` is recorded.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn upload_url(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String, Box<dyn std::error::Error>>;
+
+    async fn put(
+        &self,
+        key: &str,
+        bytes: Vec<u8>,
+        content_type: &str,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Deleting a key that doesn't exist is not an error.
+    async fn delete(&self, key: &str) -> Result<(), Box<dyn std::error::Error>>;
+
+    fn public_url(&self, key: &str) -> String;
+
+    /// The key behind a URL returned by `public_url`.
+    fn key_for_url(&self, url: &str) -> Option<String> {
+        url.strip_prefix(&self.public_url("")).map(str::to_string)
+    }
+}
@@ -0,0 +1,103 @@
+use async_trait::async_trait;
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::{
+    config::{Credentials, Region, SharedCredentialsProvider},
+    presigning::PresigningConfig,
+    primitives::ByteStream,
+    Client,
+};
+use std::time::Duration;
+
+use super::Storage;
+
+pub struct S3 {
+    pub client: Client,
+    pub bucket: String,
+    pub region: String,
+}
+
+impl S3 {
+    pub async fn init(
+        access_key_id: String,
+        secret_access_key: String,
+        region: String,
+        bucket: String,
+        provider_name: &'static str,
+    ) -> Result<Self, aws_sdk_s3::Error> {
+        let cred = Credentials::new(access_key_id, secret_access_key, None, None, provider_name);
+
+        let config = aws_config::SdkConfig::builder()
+            .credentials_provider(SharedCredentialsProvider::new(cred))
+            .region(Region::new(region.clone()))
+            .behavior_version(BehaviorVersion::latest())
+            .build();
+
+        let client = Client::new(&config);
+
+        Ok(Self {
+            client,
+            bucket,
+            region,
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for S3 {
+    async fn upload_url(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let pre = PresigningConfig::builder()
+            .expires_in(Duration::from_secs(super::UPLOAD_URL_TTL_SECS))
+            .build()?;
+
+        let url = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .presigned(pre)
+            .await?;
+
+        Ok(url.uri().to_string())
+    }
+
+    async fn put(
+        &self,
+        key: &str,
+        bytes: Vec<u8>,
+        content_type: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .body(ByteStream::from(bytes))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        format!(
+            "https://s3.{}.amazonaws.com/{}/{}",
+            self.region, self.bucket, key
+        )
+    }
+}
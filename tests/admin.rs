@@ -0,0 +1,238 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use libsql::params;
+use serde_json::json;
+
+use common::PASSWORD;
+
+/// Signs `username` up and promotes them to `role`, returning a token that
+/// carries the new role.
+async fn staff<S, B>(app: &common::TestApp<S>, username: &str, role: &str) -> common::User
+where
+    S: actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse<B>,
+        Error = actix_web::Error,
+    >,
+    B: actix_web::body::MessageBody,
+{
+    let mut user = app.signup(username).await;
+    app.conn
+        .execute(
+            "UPDATE users SET role = ?1 WHERE id = ?2",
+            params![role, user.id.clone()],
+        )
+        .await
+        .unwrap();
+
+    let res = app
+        .post(
+            "/auth/login",
+            json!({ "user": username, "password": PASSWORD }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+    user.access = res.body["tokens"]["access_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    user
+}
+
+#[actix_web::test]
+async fn admin_routes_need_the_right_role() {
+    let app = common::spawn().await;
+    let user = app.signup("regular").await;
+    let moderator = staff(&app, "moderator", "moderator").await;
+
+    let res = app.get("/admin/users", None).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.code(), "missing_token");
+
+    for token in [&user.access, &moderator.access] {
+        let res = app.get("/admin/users", Some(token)).await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN);
+        assert_eq!(res.code(), "insufficient_role");
+    }
+
+    let post = app.create_post(&moderator, "mine").await;
+    let res = app
+        .delete(&format!("/admin/posts/{}", post), Some(&user.access))
+        .await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    assert_eq!(res.code(), "insufficient_role");
+}
+
+#[actix_web::test]
+async fn moderators_remove_anyones_posts() {
+    let app = common::spawn().await;
+    let author = app.signup("author").await;
+    let moderator = staff(&app, "moderator", "moderator").await;
+    let post = app.create_post(&author, "spam").await;
+
+    let res = app
+        .delete(&format!("/admin/posts/{}", post), Some(&moderator.access))
+        .await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+    let res = app
+        .delete(&format!("/admin/posts/{}", post), Some(&moderator.access))
+        .await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+    let res = app.get("/posts/list", Some(&author.access)).await;
+    assert!(res.body["items"].as_array().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn admins_manage_users() {
+    let app = common::spawn().await;
+    let admin = staff(&app, "admin", "admin").await;
+    let user = app.signup("regular").await;
+
+    let res = app.get("/admin/users?count=10", Some(&admin.access)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["items"].as_array().unwrap().len(), 2);
+
+    let res = app
+        .put(
+            &format!("/admin/users/{}/role", admin.id),
+            json!({ "role": "user" }),
+            Some(&admin.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "cannot_change_own_role");
+
+    let res = app
+        .put(
+            &format!("/admin/users/{}/role", user.id),
+            json!({ "role": "moderator" }),
+            Some(&admin.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["role"], "moderator");
+
+    // Tokens issued before the change, even earlier in the same second, stop
+    // working.
+    let res = app.get("/profiles/sessions", Some(&user.access)).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+
+    let res = app
+        .post(
+            "/auth/login",
+            json!({ "user": "regular", "password": PASSWORD }),
+            None,
+        )
+        .await;
+    let access = res.body["tokens"]["access_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let res = app.delete("/admin/posts/no-such-post", Some(&access)).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+    let res = app
+        .post(
+            &format!("/admin/users/{}/deactivate", user.id),
+            json!({}),
+            Some(&admin.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+    let res = app
+        .post(
+            "/auth/login",
+            json!({ "user": "regular", "password": PASSWORD }),
+            None,
+        )
+        .await;
+    assert!(res.status.is_client_error(), "{}", res.body);
+
+    let res = app
+        .post(
+            &format!("/admin/users/{}/deactivate", admin.id),
+            json!({}),
+            Some(&admin.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "cannot_deactivate_self");
+}
+
+#[actix_web::test]
+async fn admins_preview_email_templates() {
+    let app = common::spawn().await;
+    let user = app.signup("regular").await;
+    let admin = staff(&app, "admin", "admin").await;
+
+    let res = app.get("/admin/emails", Some(&user.access)).await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+
+    let res = app.get("/admin/emails", Some(&admin.access)).await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+    assert_eq!(res.body["locales"], json!(["en", "hi"]));
+    let templates = res.body["templates"].as_array().unwrap();
+    assert_eq!(templates.len(), 5);
+
+    for template in templates {
+        for locale in ["en", "hi"] {
+            let res = app
+                .get(
+                    &format!(
+                        "/admin/emails/{}/preview?locale={}",
+                        template.as_str().unwrap(),
+                        locale
+                    ),
+                    Some(&admin.access),
+                )
+                .await;
+            assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+            assert!(!res.body["subject"].as_str().unwrap().is_empty());
+            assert!(res.body["text"].as_str().unwrap().contains("Asha"));
+            assert!(res.body["html"].as_str().unwrap().contains("Asha"));
+        }
+    }
+
+    let res = app
+        .get("/admin/emails/otp/preview", Some(&admin.access))
+        .await;
+    assert_eq!(res.body["subject"], "Your OnCampus verification code");
+    assert!(res.body["text"]
+        .as_str()
+        .unwrap()
+        .contains("Your verification code is A1b2C3."));
+
+    let res = app
+        .get(
+            "/admin/emails/digest/preview?locale=hi&format=html",
+            Some(&admin.access),
+        )
+        .await;
+    let html = res.body.as_str().unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains(r#"<html lang="hi">"#));
+    assert!(html.contains("आपके कैंपस में लोकप्रिय"));
+
+    let res = app
+        .get(
+            "/admin/emails/welcome/preview?format=text",
+            Some(&admin.access),
+        )
+        .await;
+    assert!(res.body.as_str().unwrap().starts_with("Hi Asha,"));
+
+    let res = app
+        .get("/admin/emails/nope/preview", Some(&admin.access))
+        .await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+    let res = app
+        .get("/admin/emails/otp/preview?locale=xx", Some(&admin.access))
+        .await;
+    assert_eq!(res.code(), "malformed_query");
+}
@@ -0,0 +1,502 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use oncampus::auth::domains::DomainPolicy;
+use oncampus::models::institution::Institution;
+use oncampus::models::user::User;
+use serde_json::json;
+
+use common::PASSWORD;
+
+#[actix_web::test]
+async fn register_verify_login_refresh_logout() {
+    let app = common::spawn().await;
+
+    let res = app.register("alice").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert_eq!(res.body["user"]["username"], "alice");
+    assert_eq!(res.body["user"]["institution"]["name"], "dcrustm.org");
+
+    let res = app
+        .post(
+            "/auth/login",
+            json!({ "user": "alice", "password": PASSWORD }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    assert_eq!(res.code(), "account_not_verified");
+
+    let res = app
+        .post(
+            "/auth/send-otp",
+            json!({ "email": "alice@dcrustm.org" }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    let otp = app.inbox.otp_for("alice@dcrustm.org").await;
+
+    let res = app
+        .post(
+            "/auth/verify-otp",
+            json!({ "email": "alice@dcrustm.org", "otp": otp }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.body["tokens"]["access_token"].is_string());
+
+    let res = app
+        .post(
+            "/auth/login",
+            json!({ "user": "alice", "password": PASSWORD }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["user"]["email"], "alice@dcrustm.org");
+    let refresh = res.body["tokens"]["refresh_token"].as_str().unwrap();
+
+    let res = app
+        .post("/auth/refresh", json!({ "token": refresh }), None)
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    let access = res.body["tokens"]["access_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let refresh = res.body["tokens"]["refresh_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let res = app.get("/profiles/sessions", Some(&access)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    // One session from verifying the email, one from logging in.
+    assert_eq!(res.body.as_array().unwrap().len(), 2);
+
+    let res = app
+        .post(
+            "/profiles/logout",
+            json!({ "token": refresh }),
+            Some(&access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let res = app.get("/profiles/sessions", Some(&access)).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.code(), "token_revoked");
+
+    let res = app
+        .post("/auth/refresh", json!({ "token": refresh }), None)
+        .await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn register_rejects_bad_input() {
+    let app = common::spawn().await;
+
+    let res = app
+        .post(
+            "/auth/register",
+            json!({
+                "email": "bob@example.com",
+                "password": PASSWORD,
+                "username": "bobby",
+                "first_name": "Bob",
+                "last_name": "Smith",
+                "roll": "1",
+                "dob": "2003-04-05",
+            }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "email_domain_not_allowed");
+
+    let res = app
+        .post(
+            "/auth/register",
+            json!({
+                "email": "bob@dcrustm.org",
+                "password": "short",
+                "username": "bobby",
+                "first_name": "Bob",
+                "last_name": "Smith",
+                "roll": "1",
+                "dob": "2003-04-05",
+            }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "validation_failed");
+    assert!(res.body["error"]["details"]["password"].is_array());
+
+    let res = app
+        .post(
+            "/auth/register",
+            json!({ "email": "bob@dcrustm.org" }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "malformed_body");
+
+    assert_eq!(app.register("bobby").await.status, StatusCode::CREATED);
+    let res = app.register("bobby").await;
+    assert_eq!(res.status, StatusCode::CONFLICT);
+    assert_eq!(res.code(), "email_taken");
+
+    for (field, value, code) in [
+        ("username", "bobby", "username_taken"),
+        ("roll", "roll-bobby", "roll_taken"),
+    ] {
+        let mut body = json!({
+            "email": "robert@dcrustm.org",
+            "password": PASSWORD,
+            "username": "robert",
+            "first_name": "Robert",
+            "last_name": "Smith",
+            "roll": "roll-robert",
+            "dob": "2003-04-05",
+        });
+        body[field] = json!(value);
+        let res = app.post("/auth/register", body, None).await;
+        assert_eq!(res.status, StatusCode::CONFLICT, "{}", res.body);
+        assert_eq!(res.code(), code);
+    }
+}
+
+#[actix_web::test]
+async fn restarting_applies_changed_roll_formats() {
+    let app = common::spawn().await;
+    let policy = DomainPolicy::parse("dcrustm.org=[0-9]{8}").unwrap();
+    Institution::seed(&policy, &app.conn).await.unwrap();
+
+    let res = app.register("frank").await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST, "{}", res.body);
+    assert_eq!(res.code(), "invalid_roll");
+}
+
+#[actix_web::test]
+async fn otp_errors() {
+    let app = common::spawn().await;
+    app.register("carol").await;
+    let email = "carol@dcrustm.org";
+
+    let res = app
+        .post(
+            "/auth/verify-otp",
+            json!({ "email": email, "otp": "AAAAAA" }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "otp_not_found");
+
+    app.post("/auth/send-otp", json!({ "email": email }), None)
+        .await;
+    let otp = app.inbox.otp_for(email).await;
+
+    let res = app
+        .post("/auth/send-otp", json!({ "email": email }), None)
+        .await;
+    assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.code(), "otp_cooldown");
+
+    let wrong = if otp == "AAAAAA" { "BBBBBB" } else { "AAAAAA" };
+    let res = app
+        .post(
+            "/auth/verify-otp",
+            json!({ "email": email, "otp": wrong }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "otp_invalid");
+
+    let res = app
+        .post(
+            "/auth/verify-otp",
+            json!({ "email": email, "otp": otp }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    // Verified and unknown addresses get the same answer as unverified ones,
+    // but no mail.
+    for address in [email, "nobody@dcrustm.org"] {
+        let res = app
+            .post(
+                "/auth/resend-verification",
+                json!({ "email": address }),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+    }
+    let mut rows = app
+        .conn
+        .query(
+            "SELECT recipients, subject FROM email_outbox ORDER BY created_at, rowid",
+            (),
+        )
+        .await
+        .unwrap();
+    let mut queued = vec![];
+    while let Some(row) = rows.next().await.unwrap() {
+        queued.push((row.get::<String>(0).unwrap(), row.get::<String>(1).unwrap()));
+    }
+    assert_eq!(
+        queued,
+        [
+            (
+                email.to_string(),
+                "Your OnCampus verification code".to_string()
+            ),
+            (email.to_string(), "Welcome to OnCampus".to_string()),
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn wrong_codes_lock_the_otp() {
+    let app = common::spawn().await;
+    app.register("erin").await;
+    let email = "erin@dcrustm.org";
+
+    app.post("/auth/send-otp", json!({ "email": email }), None)
+        .await;
+    let otp = app.inbox.otp_for(email).await;
+    let wrong = if otp == "AAAAAA" { "BBBBBB" } else { "AAAAAA" };
+
+    for attempt in 1..=5 {
+        let res = app
+            .post(
+                "/auth/verify-otp",
+                json!({ "email": email, "otp": wrong }),
+                None,
+            )
+            .await;
+        if attempt < 5 {
+            assert_eq!(res.code(), "otp_invalid", "{}", res.body);
+        } else {
+            assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(res.code(), "otp_locked");
+        }
+    }
+
+    // Even the right code is refused while locked, and no new one is sent.
+    let res = app
+        .post(
+            "/auth/verify-otp",
+            json!({ "email": email, "otp": otp }),
+            None,
+        )
+        .await;
+    assert_eq!(res.code(), "otp_locked");
+
+    let res = app
+        .post("/auth/send-otp", json!({ "email": email }), None)
+        .await;
+    assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.code(), "otp_locked");
+}
+
+#[actix_web::test]
+async fn purging_keeps_unverified_accounts_that_liked_or_commented() {
+    let app = common::spawn().await;
+    let alice = app.signup("alice").await;
+    let post = app.create_post(&alice, "old news").await;
+    for username in ["liker", "commenter", "lurker"] {
+        app.register(username).await;
+    }
+    app.conn
+        .execute_batch(&format!(
+            r#"
+            UPDATE users SET created_at = datetime('now', '-30 days') WHERE is_active = FALSE;
+            INSERT INTO post_likes (id, post, user)
+            SELECT 'like', '{post}', id FROM users WHERE username = 'liker';
+            INSERT INTO post_comments (id, user, post, text)
+            SELECT 'comment', id, '{post}', 'hi' FROM users WHERE username = 'commenter';
+            "#
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(User::purge_unverified(7, &app.conn).await.unwrap(), 1);
+
+    let mut rows = app
+        .conn
+        .query("SELECT username FROM users ORDER BY username", ())
+        .await
+        .unwrap();
+    let mut left = vec![];
+    while let Some(row) = rows.next().await.unwrap() {
+        left.push(row.get::<String>(0).unwrap());
+    }
+    assert_eq!(left, ["alice", "commenter", "liker"]);
+}
+
+#[actix_web::test]
+async fn login_rejects_wrong_credentials() {
+    let app = common::spawn().await;
+    app.signup("dave").await;
+
+    for (user, password) in [("dave", "wrong password"), ("nobody", PASSWORD)] {
+        let res = app
+            .post(
+                "/auth/login",
+                json!({ "user": user, "password": password }),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(res.code(), "invalid_credentials");
+    }
+}
+
+#[actix_web::test]
+async fn protected_routes_need_a_valid_access_token() {
+    let app = common::spawn().await;
+    let user = app.signup("erin").await;
+
+    let res = app.get("/posts/list", None).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.code(), "missing_token");
+
+    let res = app.get("/posts/list", Some("not-a-token")).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.code(), "invalid_token");
+
+    let res = app.get("/posts/list", Some(&user.refresh)).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.code(), "wrong_token_type");
+
+    let res = app
+        .post("/auth/refresh", json!({ "token": user.access }), None)
+        .await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+
+    let res = app.get("/posts/list", Some(&user.access)).await;
+    assert_eq!(res.status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn reusing_a_refresh_token_revokes_the_session() {
+    let app = common::spawn().await;
+    let user = app.signup("frank").await;
+
+    // Cache the access token as valid before the session is revoked.
+    let res = app.get("/profiles/sessions", Some(&user.access)).await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let res = app
+        .post("/auth/refresh", json!({ "token": user.refresh }), None)
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    let rotated = res.body["tokens"]["refresh_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let res = app
+        .post("/auth/refresh", json!({ "token": user.refresh }), None)
+        .await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.code(), "token_reused");
+    let alert = app
+        .inbox
+        .find(&user.email, "Security alert for your OnCampus account")
+        .await;
+    assert!(alert.contains("We signed out a session"));
+
+    let res = app
+        .post("/auth/refresh", json!({ "token": rotated }), None)
+        .await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.code(), "session_expired");
+
+    let res = app.get("/profiles/sessions", Some(&user.access)).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.code(), "token_revoked");
+}
+
+#[actix_web::test]
+async fn password_reset_signs_out_everywhere() {
+    let app = common::spawn().await;
+    let user = app.signup("grace").await;
+
+    let res = app.get("/profiles/sessions", Some(&user.access)).await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let res = app
+        .post(
+            "/auth/forgot-password",
+            json!({ "email": "nobody@dcrustm.org" }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(app.inbox.count_for("nobody@dcrustm.org"), 0);
+
+    let res = app
+        .post(
+            "/auth/forgot-password",
+            json!({ "email": user.email }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    let otp = app.inbox.otp_for(&user.email).await;
+
+    let res = app
+        .post(
+            "/auth/reset-password",
+            json!({ "email": user.email, "otp": otp, "password": "a new password" }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+    let alert = app
+        .inbox
+        .find(&user.email, "Security alert for your OnCampus account")
+        .await;
+    assert!(alert.contains("Your password was changed"));
+
+    let res = app.get("/profiles/sessions", Some(&user.access)).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+
+    let res = app
+        .post(
+            "/auth/login",
+            json!({ "user": "grace", "password": PASSWORD }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+
+    let res = app
+        .post(
+            "/auth/login",
+            json!({ "user": "grace", "password": "a new password" }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn jwks_publishes_the_signing_key() {
+    let app = common::spawn().await;
+
+    let res = app.get("/.well-known/jwks.json", None).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["keys"][0]["kid"], "test");
+    assert_eq!(res.body["keys"][0]["kty"], "RSA");
+}
@@ -0,0 +1,335 @@
+// Boots the real app against an in-memory database, a mailer that keeps
+// messages in memory and a freshly generated RSA key.
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::{test, web, App};
+use async_trait::async_trait;
+use lettre::address::Envelope;
+use rsa::pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::RsaPrivateKey;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use oncampus::auth::cache::{RevocationCache, DEFAULT_CAPACITY, DEFAULT_TTL};
+use oncampus::auth::domains::DomainPolicy;
+use oncampus::auth::token::JWT;
+use oncampus::config::{Branding, DatabaseConfig};
+use oncampus::db::Db;
+use oncampus::email::transport::{Memory, Transport};
+use oncampus::email::Email;
+use oncampus::models::institution::Institution;
+use oncampus::storage::{local::LocalStorage, Storage};
+use oncampus::{jobs, AppState};
+
+pub const PASSWORD: &str = "correct horse";
+
+/// Fails the first `failures` sends, then delivers to `memory`.
+struct Flaky {
+    failures: AtomicUsize,
+    memory: Arc<Memory>,
+}
+
+#[async_trait]
+impl Transport for Flaky {
+    async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let failed = self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+        if failed.is_ok() {
+            return Err("connection refused".into());
+        }
+        self.memory.send(envelope, message).await
+    }
+}
+
+/// Mail delivered by the app, read the way a user would.
+pub struct Inbox {
+    memory: Arc<Memory>,
+    /// How many messages to each address have been read.
+    read: Mutex<HashMap<String, usize>>,
+}
+
+impl Inbox {
+    fn delivered(&self, email: &str) -> Vec<String> {
+        self.memory
+            .sent()
+            .into_iter()
+            .filter(|m| m.to.iter().any(|to| to == email))
+            .map(|m| m.message)
+            .collect()
+    }
+
+    /// The code in the latest unread message to `email` that has one. Mail
+    /// goes out in the background, so this waits for it for a little while.
+    pub async fn otp_for(&self, email: &str) -> String {
+        let code = regex::Regex::new(r"is ([A-Za-z0-9]{6})\b").unwrap();
+        for _ in 0..200 {
+            let delivered = self.delivered(email);
+            let unread = {
+                let mut read = self.read.lock().unwrap();
+                let seen = read.entry(email.to_string()).or_default();
+                let unread = delivered[*seen..].to_vec();
+                *seen = delivered.len();
+                unread
+            };
+            if let Some(found) = unread.iter().rev().find_map(|m| code.captures(m)) {
+                return found[1].to_string();
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no OTP was sent to {}", email);
+    }
+
+    /// Waits for a message to `email` whose subject is `subject`.
+    pub async fn find(&self, email: &str, subject: &str) -> String {
+        let header = format!("Subject: {}\r\n", subject);
+        for _ in 0..200 {
+            if let Some(message) = self
+                .delivered(email)
+                .into_iter()
+                .find(|m| m.contains(&header))
+            {
+                return message;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no email titled {:?} was sent to {}", subject, email);
+    }
+
+    pub fn count_for(&self, email: &str) -> usize {
+        self.delivered(email).len()
+    }
+}
+
+/// Generating a key is slow, so every test in a binary shares one.
+fn keys() -> &'static (String, String) {
+    static KEYS: OnceLock<(String, String)> = OnceLock::new();
+    KEYS.get_or_init(|| {
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let private = key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+        let public = key
+            .to_public_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        (private, public)
+    })
+}
+
+pub struct Response {
+    pub status: StatusCode,
+    pub body: Value,
+}
+
+impl Response {
+    /// The `code` of an error response.
+    pub fn code(&self) -> &str {
+        self.body["error"]["code"].as_str().unwrap_or_default()
+    }
+}
+
+pub struct User {
+    pub id: String,
+    pub email: String,
+    pub username: String,
+    pub access: String,
+    pub refresh: String,
+}
+
+pub struct TestApp<S> {
+    service: S,
+    pub inbox: Inbox,
+    pub conn: libsql::Connection,
+}
+
+pub async fn spawn() -> TestApp<
+    impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>,
+> {
+    spawn_flaky(0).await
+}
+
+/// Like `spawn`, but the first `failures` emails fail to send.
+pub async fn spawn_flaky(
+    failures: usize,
+) -> TestApp<
+    impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>,
+> {
+    let db = Db::init(&DatabaseConfig::Memory).await.unwrap();
+    db.migrate().await.unwrap();
+    let domains = DomainPolicy::parse("dcrustm.org").unwrap();
+    Institution::seed(&domains, db.get_conn()).await.unwrap();
+    let conn = db.connect().await.unwrap();
+    let db = web::Data::new(db);
+
+    let memory = Arc::new(Memory::default());
+    let mailer = Email::new(
+        "OnCampus <test@oncampus.local>".parse().unwrap(),
+        Branding {
+            name: "OnCampus".to_string(),
+            color: "#2563eb".to_string(),
+            logo_url: None,
+            url: Some("https://oncampus.local".to_string()),
+            support_email: Some("help@oncampus.local".to_string()),
+        },
+    );
+    jobs::deliver_email(
+        db.clone(),
+        Arc::new(Flaky {
+            failures: AtomicUsize::new(failures),
+            memory: memory.clone(),
+        }),
+        mailer.queued(),
+    );
+
+    let (private, public) = keys();
+    let jwt = JWT::new(
+        ("test", private.as_str()),
+        &[("test".to_string(), public.clone())],
+    )
+    .unwrap();
+
+    let media = std::env::temp_dir().join(format!("oncampus-test-{}", Uuid::new_v4()));
+    let local = Arc::new(LocalStorage::init(media, "http://localhost".to_string()).unwrap());
+
+    let state = AppState {
+        db,
+        mailer: web::Data::new(mailer),
+        jwt: web::Data::new(jwt),
+        revocations: web::Data::new(RevocationCache::new(DEFAULT_CAPACITY, DEFAULT_TTL)),
+        storage: web::Data::from(local.clone() as Arc<dyn Storage>),
+        local_storage: Some(web::Data::from(local)),
+    };
+
+    let service = test::init_service(App::new().configure(oncampus::configure(state))).await;
+
+    TestApp {
+        service,
+        inbox: Inbox {
+            memory,
+            read: Mutex::new(HashMap::new()),
+        },
+        conn,
+    }
+}
+
+impl<S, B> TestApp<S>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    pub async fn call(&self, req: test::TestRequest, token: Option<&str>) -> Response {
+        let req = match token {
+            Some(token) => req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token))),
+            None => req,
+        };
+        let res = test::call_service(&self.service, req.to_request()).await;
+        let status = res.status();
+        let bytes = test::read_body(res).await;
+        let body = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+        Response { status, body }
+    }
+
+    pub async fn get(&self, uri: &str, token: Option<&str>) -> Response {
+        self.call(test::TestRequest::get().uri(uri), token).await
+    }
+
+    pub async fn post(&self, uri: &str, body: Value, token: Option<&str>) -> Response {
+        self.call(test::TestRequest::post().uri(uri).set_json(body), token)
+            .await
+    }
+
+    pub async fn patch(&self, uri: &str, body: Value, token: Option<&str>) -> Response {
+        self.call(test::TestRequest::patch().uri(uri).set_json(body), token)
+            .await
+    }
+
+    pub async fn put(&self, uri: &str, body: Value, token: Option<&str>) -> Response {
+        self.call(test::TestRequest::put().uri(uri).set_json(body), token)
+            .await
+    }
+
+    pub async fn delete(&self, uri: &str, token: Option<&str>) -> Response {
+        self.call(test::TestRequest::delete().uri(uri), token).await
+    }
+
+    /// Registers `username` without verifying the email address.
+    pub async fn register(&self, username: &str) -> Response {
+        self.post(
+            "/auth/register",
+            json!({
+                "email": format!("{}@dcrustm.org", username),
+                "password": PASSWORD,
+                "username": username,
+                "first_name": "Test",
+                "last_name": username,
+                "roll": format!("roll-{}", username),
+                "dob": "2003-04-05",
+            }),
+            None,
+        )
+        .await
+    }
+
+    /// Registers and verifies `username`, returning its first session.
+    pub async fn signup(&self, username: &str) -> User {
+        let res = self.register(username).await;
+        assert_eq!(res.status, StatusCode::CREATED, "{}", res.body);
+        let id = res.body["user"]["id"].as_str().unwrap().to_string();
+        let email = format!("{}@dcrustm.org", username);
+
+        let res = self
+            .post("/auth/send-otp", json!({ "email": email }), None)
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+
+        let otp = self.inbox.otp_for(&email).await;
+        let res = self
+            .post(
+                "/auth/verify-otp",
+                json!({ "email": email, "otp": otp }),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+
+        User {
+            id,
+            email,
+            username: username.to_string(),
+            access: res.body["tokens"]["access_token"]
+                .as_str()
+                .unwrap()
+                .to_string(),
+            refresh: res.body["tokens"]["refresh_token"]
+                .as_str()
+                .unwrap()
+                .to_string(),
+        }
+    }
+
+    pub async fn create_post(&self, user: &User, text: &str) -> String {
+        let res = self
+            .post(
+                "/posts/create",
+                json!({ "text": text, "public": true }),
+                Some(&user.access),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::CREATED, "{}", res.body);
+        res.body["id"].as_str().unwrap().to_string()
+    }
+}
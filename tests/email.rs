@@ -0,0 +1,340 @@
+mod common;
+
+use std::time::Duration;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::test::TestRequest;
+use libsql::{params, Connection};
+use serde_json::json;
+
+use common::PASSWORD;
+use oncampus::models::outbox::OutboxEntry;
+
+/// Waits for the worker to try sending the first message to `recipient`
+/// and returns its id and the number of attempts.
+async fn attempted(conn: &Connection, recipient: &str) -> (String, i64) {
+    for _ in 0..200 {
+        let mut rows = conn
+            .query(
+                "SELECT id, attempts FROM email_outbox WHERE recipients = ?1 AND attempts > 0",
+                params![recipient],
+            )
+            .await
+            .unwrap();
+        if let Some(row) = rows.next().await.unwrap() {
+            return (row.get(0).unwrap(), row.get(1).unwrap());
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("no email to {} was attempted", recipient);
+}
+
+/// Waits until the message titled `subject` to `recipient` is marked sent.
+async fn sent(conn: &Connection, recipient: &str, subject: &str) {
+    for _ in 0..200 {
+        let mut rows = conn
+            .query(
+                "SELECT 1 FROM email_outbox WHERE recipients = ?1 AND subject = ?2 AND sent_at IS NOT NULL",
+                params![recipient, subject],
+            )
+            .await
+            .unwrap();
+        if rows.next().await.unwrap().is_some() {
+            return;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("{:?} to {} was not sent", subject, recipient);
+}
+
+#[actix_web::test]
+async fn mail_goes_through_the_outbox() {
+    let app = common::spawn().await;
+    let user = app.signup("alice").await;
+
+    let mut rows = app
+        .conn
+        .query(
+            "SELECT subject, attempts, sent_at IS NOT NULL, length(message) FROM email_outbox WHERE recipients = ?1 ORDER BY rowid",
+            params![user.email.clone()],
+        )
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    assert_eq!(
+        row.get::<String>(0).unwrap(),
+        "Your OnCampus verification code"
+    );
+    assert_eq!(row.get::<i64>(1).unwrap(), 1);
+    assert!(row.get::<bool>(2).unwrap());
+    // The code isn't kept once it has been sent.
+    assert_eq!(row.get::<i64>(3).unwrap(), 0);
+}
+
+#[actix_web::test]
+async fn old_mail_is_pruned() {
+    let app = common::spawn().await;
+    let user = app.signup("erin").await;
+    sent(&app.conn, &user.email, "Welcome to OnCampus").await;
+
+    app.conn
+        .execute(
+            r#"
+            INSERT INTO email_outbox (id, sender, recipients, subject, message, next_attempt_at, failed_at)
+            VALUES
+                ('old-failure', 'a@b.c', 'x@dcrustm.org', 'Old', X'00', 0, datetime('now', '-8 days')),
+                ('new-failure', 'a@b.c', 'x@dcrustm.org', 'New', X'00', 0, datetime('now', '-1 days')),
+                ('pending', 'a@b.c', 'x@dcrustm.org', 'Pending', X'00', 9999999999, NULL)
+            "#,
+            (),
+        )
+        .await
+        .unwrap();
+    app.conn
+        .execute(
+            "UPDATE email_outbox SET sent_at = datetime('now', '-8 days') WHERE subject = 'Welcome to OnCampus'",
+            (),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(OutboxEntry::prune(7, &app.conn).await.unwrap(), 2);
+
+    let mut rows = app
+        .conn
+        .query(
+            "SELECT subject FROM email_outbox WHERE recipients = ?1 OR id IN ('new-failure', 'pending') ORDER BY rowid",
+            params![user.email.clone()],
+        )
+        .await
+        .unwrap();
+    let mut left = vec![];
+    while let Some(row) = rows.next().await.unwrap() {
+        left.push(row.get::<String>(0).unwrap());
+    }
+    assert_eq!(left, ["Your OnCampus verification code", "New", "Pending"]);
+}
+
+#[actix_web::test]
+async fn failed_mail_is_kept_and_can_be_retried() {
+    let app = common::spawn_flaky(1).await;
+    let email = "bobby@dcrustm.org";
+
+    app.register("bobby").await;
+    let res = app
+        .post("/auth/send-otp", json!({ "email": email }), None)
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    let (id, attempts) = attempted(&app.conn, email).await;
+    assert_eq!(attempts, 1);
+    assert_eq!(app.inbox.count_for(email), 0);
+
+    // Only the first send fails, so the admin's own mail goes through.
+    let admin = app.signup("admin").await;
+    app.conn
+        .execute(
+            "UPDATE users SET role = 'admin' WHERE id = ?1",
+            params![admin.id.clone()],
+        )
+        .await
+        .unwrap();
+    let res = app
+        .post(
+            "/auth/login",
+            json!({ "user": "admin", "password": PASSWORD }),
+            None,
+        )
+        .await;
+    let access = res.body["tokens"]["access_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    sent(&app.conn, &admin.email, "Welcome to OnCampus").await;
+
+    let res = app.get("/admin/outbox?status=pending", Some(&access)).await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+    let items = res.body["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["id"], id.as_str());
+    assert_eq!(items[0]["last_error"], "connection refused");
+    assert!(items[0]["next_attempt_at"].is_i64());
+
+    let res = app
+        .post(
+            &format!("/admin/outbox/{}/retry", id),
+            json!({}),
+            Some(&access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+    // Skip the backoff as if every retry had failed too.
+    app.conn
+        .execute(
+            "UPDATE email_outbox SET failed_at = CURRENT_TIMESTAMP WHERE id = ?1",
+            params![id.clone()],
+        )
+        .await
+        .unwrap();
+    let res = app.get("/admin/outbox?status=failed", Some(&access)).await;
+    assert_eq!(res.body["items"][0]["id"], id.as_str());
+
+    let res = app
+        .post(
+            &format!("/admin/outbox/{}/retry", id),
+            json!({}),
+            Some(&access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+
+    let otp = app.inbox.otp_for(email).await;
+    let res = app
+        .post(
+            "/auth/verify-otp",
+            json!({ "email": email, "otp": otp }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+    sent(&app.conn, email, "Welcome to OnCampus").await;
+
+    // A verification code and a welcome each.
+    let res = app.get("/admin/outbox?status=sent", Some(&access)).await;
+    assert_eq!(res.body["items"].as_array().unwrap().len(), 4);
+
+    let res = app.get("/admin/outbox?status=bogus", Some(&access)).await;
+    assert_eq!(res.code(), "malformed_query");
+}
+
+#[actix_web::test]
+async fn mail_has_text_and_html_bodies() {
+    let app = common::spawn().await;
+    let user = app.signup("carol").await;
+
+    let welcome = app.inbox.find(&user.email, "Welcome to OnCampus").await;
+    assert!(welcome.contains("Content-Type: multipart/alternative"));
+    assert!(welcome.contains("Content-Type: text/plain; charset=utf-8"));
+    assert!(welcome.contains("Content-Type: text/html; charset=utf-8"));
+    assert!(welcome.contains("Hi Test,"));
+    assert!(welcome.contains("Open OnCampus: https://oncampus.local"));
+    assert!(welcome.contains("Content-Transfer-Encoding: quoted-printable"));
+}
+
+#[actix_web::test]
+async fn mail_is_written_in_the_users_language() {
+    let app = common::spawn().await;
+
+    let res = app
+        .call(
+            TestRequest::post()
+                .uri("/auth/register")
+                .insert_header((header::ACCEPT_LANGUAGE, "hi-IN,hi;q=0.9,en;q=0.8"))
+                .set_json(json!({
+                    "email": "priya@dcrustm.org",
+                    "password": PASSWORD,
+                    "username": "priya",
+                    "first_name": "Priya",
+                    "last_name": "Sharma",
+                    "roll": "roll-priya",
+                    "dob": "2003-04-05",
+                })),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::CREATED, "{}", res.body);
+    app.post(
+        "/auth/send-otp",
+        json!({ "email": "priya@dcrustm.org" }),
+        None,
+    )
+    .await;
+    sent(&app.conn, "priya@dcrustm.org", "आपका OnCampus सत्यापन कोड").await;
+
+    // Users can switch languages later on.
+    let user = app.signup("dave").await;
+    let res = app
+        .call(
+            TestRequest::post()
+                .uri("/profiles/update")
+                .set_form([("locale", "hi")]),
+            Some(&user.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+    app.post(
+        "/auth/forgot-password",
+        json!({ "email": user.email }),
+        None,
+    )
+    .await;
+    sent(&app.conn, &user.email, "अपना OnCampus पासवर्ड रीसेट करें").await;
+
+    let res = app
+        .call(
+            TestRequest::post()
+                .uri("/profiles/update")
+                .set_form([("locale", "xx")]),
+            Some(&user.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn codes_that_cannot_be_queued_are_withdrawn() {
+    let app = common::spawn().await;
+    let user = app.signup("fiona").await;
+    let email = "gina@dcrustm.org";
+    app.register("gina").await;
+
+    app.conn
+        .execute(
+            r#"
+            CREATE TRIGGER outbox_down BEFORE INSERT ON email_outbox
+            BEGIN SELECT RAISE(ABORT, 'outbox unavailable'); END
+            "#,
+            (),
+        )
+        .await
+        .unwrap();
+    let res = app
+        .post("/auth/send-otp", json!({ "email": email }), None)
+        .await;
+    assert_eq!(res.status, StatusCode::INTERNAL_SERVER_ERROR);
+    let res = app
+        .post(
+            "/auth/forgot-password",
+            json!({ "email": user.email }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::INTERNAL_SERVER_ERROR);
+    app.conn
+        .execute("DROP TRIGGER outbox_down", ())
+        .await
+        .unwrap();
+
+    // Asking again straight away isn't held back by a cooldown.
+    let res = app
+        .post("/auth/send-otp", json!({ "email": email }), None)
+        .await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+    let otp = app.inbox.otp_for(email).await;
+    let res = app
+        .post(
+            "/auth/verify-otp",
+            json!({ "email": email, "otp": otp }),
+            None,
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+
+    app.post(
+        "/auth/forgot-password",
+        json!({ "email": user.email }),
+        None,
+    )
+    .await;
+    sent(&app.conn, &user.email, "Reset your OnCampus password").await;
+}
@@ -0,0 +1,292 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use oncampus::storage::local::MAX_UPLOAD_BYTES;
+use serde_json::json;
+
+#[actix_web::test]
+async fn posts_show_up_in_other_users_feeds() {
+    let app = common::spawn().await;
+    let alice = app.signup("alice").await;
+    let bob = app.signup("bobby").await;
+
+    let post = app.create_post(&alice, "hello campus").await;
+
+    let res = app.get("/posts/list", Some(&bob.access)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let items = res.body["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["id"], post.as_str());
+    assert_eq!(items[0]["username"], "alice");
+    assert_eq!(items[0]["text"], "hello campus");
+
+    // The friends feed only has posts from people you follow.
+    let res = app.get("/posts/list/friends", Some(&bob.access)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.body["items"].as_array().unwrap().is_empty());
+
+    let res = app
+        .post(
+            &format!("/profiles/{}/follow", alice.id),
+            json!({}),
+            Some(&bob.access),
+        )
+        .await;
+    assert!(res.status.is_success(), "{}", res.body);
+
+    let res = app.get("/posts/list/friends", Some(&bob.access)).await;
+    assert_eq!(res.body["items"][0]["id"], post.as_str());
+}
+
+#[actix_web::test]
+async fn create_post_rejects_bad_input() {
+    let app = common::spawn().await;
+    let alice = app.signup("alice").await;
+
+    let res = app
+        .post(
+            "/posts/create",
+            json!({ "text": "x".repeat(1001), "public": true }),
+            Some(&alice.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "validation_failed");
+
+    let res = app
+        .post(
+            "/posts/create",
+            json!({ "text": "pic", "public": true, "images": [{ "content_type": "text/html" }] }),
+            Some(&alice.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "invalid_content_type");
+
+    let res = app
+        .get("/posts/list?cursor=garbage", Some(&alice.access))
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "invalid_cursor");
+}
+
+#[actix_web::test]
+async fn like_and_unlike() {
+    let app = common::spawn().await;
+    let alice = app.signup("alice").await;
+    let bob = app.signup("bobby").await;
+    let post = app.create_post(&alice, "like me").await;
+
+    let res = app
+        .post(
+            &format!("/posts/like/{}", post),
+            json!({}),
+            Some(&bob.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    // Liking twice is harmless.
+    let res = app
+        .post(
+            &format!("/posts/like/{}", post),
+            json!({}),
+            Some(&bob.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let res = app
+        .get(&format!("/posts/{}/likes", post), Some(&alice.access))
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    let likes = res.body["items"].as_array().unwrap();
+    assert_eq!(likes.len(), 1);
+    assert_eq!(likes[0]["username"], "bobby");
+
+    let res = app.get("/posts/list", Some(&bob.access)).await;
+    assert_eq!(res.body["items"][0]["likes"], 1);
+    assert_eq!(res.body["items"][0]["liked_by_me"], true);
+
+    let res = app
+        .delete(&format!("/posts/like/{}", post), Some(&bob.access))
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let res = app.get("/posts/list", Some(&bob.access)).await;
+    assert_eq!(res.body["items"][0]["likes"], 0);
+    assert_eq!(res.body["items"][0]["liked_by_me"], false);
+
+    let res = app
+        .post("/posts/like/no-such-post", json!({}), Some(&bob.access))
+        .await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert_eq!(res.code(), "not_found");
+}
+
+#[actix_web::test]
+async fn comments_and_replies() {
+    let app = common::spawn().await;
+    let alice = app.signup("alice").await;
+    let bob = app.signup("bobby").await;
+    let post = app.create_post(&alice, "discuss").await;
+
+    let res = app
+        .post(
+            "/posts/comment",
+            json!({ "post": post, "text": "first" }),
+            Some(&bob.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    let comment = res.body["id"].as_str().unwrap().to_string();
+
+    let res = app
+        .post(
+            "/posts/comment",
+            json!({ "post": post, "parent": comment, "text": "reply" }),
+            Some(&alice.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    let reply = res.body["id"].as_str().unwrap().to_string();
+
+    let res = app
+        .post(
+            "/posts/comment",
+            json!({ "post": post, "parent": reply, "text": "too deep" }),
+            Some(&bob.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "reply_too_deep");
+
+    let res = app
+        .post(
+            "/posts/comment",
+            json!({ "post": "no-such-post", "text": "lost" }),
+            Some(&bob.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+    let res = app
+        .get(&format!("/posts/comments/{}", post), Some(&bob.access))
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    let comments = res.body["items"].as_array().unwrap();
+    assert_eq!(comments.len(), 1);
+    assert_eq!(comments[0]["id"], comment.as_str());
+    assert_eq!(comments[0]["replies"], 1);
+
+    let res = app
+        .get(
+            &format!("/posts/comment/{}/replies", comment),
+            Some(&bob.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["items"][0]["text"], "reply");
+
+    let res = app
+        .patch(
+            &format!("/posts/comment/{}", comment),
+            json!({ "text": "edited" }),
+            Some(&bob.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let res = app.get("/posts/list", Some(&bob.access)).await;
+    assert_eq!(res.body["items"][0]["comments"], 2);
+}
+
+#[actix_web::test]
+async fn only_owners_change_posts_and_comments() {
+    let app = common::spawn().await;
+    let alice = app.signup("alice").await;
+    let bob = app.signup("bobby").await;
+    let post = app.create_post(&alice, "mine").await;
+
+    let res = app
+        .post(
+            "/posts/comment",
+            json!({ "post": post, "text": "bob's" }),
+            Some(&bob.access),
+        )
+        .await;
+    let comment = res.body["id"].as_str().unwrap().to_string();
+
+    let res = app
+        .patch(
+            &format!("/posts/{}", post),
+            json!({ "text": "hijacked" }),
+            Some(&bob.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    assert_eq!(res.code(), "not_owner");
+
+    let res = app
+        .delete(&format!("/posts/{}", post), Some(&bob.access))
+        .await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    assert_eq!(res.code(), "not_owner");
+
+    let res = app
+        .patch(
+            &format!("/posts/comment/{}", comment),
+            json!({ "text": "hijacked" }),
+            Some(&alice.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+
+    // The post's owner may delete comments on it.
+    let res = app
+        .delete(&format!("/posts/comment/{}", comment), Some(&alice.access))
+        .await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+    let res = app
+        .patch(
+            &format!("/posts/{}", post),
+            json!({ "text": "still mine" }),
+            Some(&alice.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let res = app
+        .delete(&format!("/posts/{}", post), Some(&alice.access))
+        .await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+    let res = app
+        .delete(&format!("/posts/{}", post), Some(&alice.access))
+        .await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+    let res = app.get("/posts/list", Some(&bob.access)).await;
+    assert!(res.body["items"].as_array().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn rejected_uploads_get_error_bodies() {
+    let app = common::spawn().await;
+    let upload = |size: usize| {
+        TestRequest::put()
+            .uri("/uploads/posts/photo.png?token=guessed")
+            .insert_header(("Content-Type", "image/png"))
+            .set_payload(vec![0u8; size])
+    };
+
+    let res = app.call(upload(16), None).await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    assert_eq!(res.code(), "invalid_upload_url");
+
+    let res = app.call(upload(MAX_UPLOAD_BYTES + 1), None).await;
+    assert_eq!(res.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(res.code(), "payload_too_large");
+}
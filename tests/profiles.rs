@@ -0,0 +1,263 @@
+mod common;
+
+use std::io::Cursor;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use image::{ImageFormat, RgbImage};
+use libsql::params;
+use oncampus::profile::avatar::MAX_AVATAR_BYTES;
+use serde_json::json;
+
+#[actix_web::test]
+async fn search_finds_verified_users_a_page_at_a_time() {
+    let app = common::spawn().await;
+    let searcher = app.signup("searcher").await;
+    for name in ["student1", "student2", "student3"] {
+        app.signup(name).await;
+    }
+    app.register("student4").await;
+
+    let res = app
+        .get(
+            "/profiles/search?string=STUDENT&count=2",
+            Some(&searcher.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    let names: Vec<_> = res.body["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["username"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["student1", "student2"]);
+    let cursor = res.body["next_cursor"].as_str().unwrap().to_string();
+
+    let res = app
+        .get(
+            &format!("/profiles/search?string=student&count=2&cursor={}", cursor),
+            Some(&searcher.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    let items = res.body["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["username"], "student3");
+    assert!(res.body["next_cursor"].is_null());
+
+    let res = app
+        .get(
+            "/profiles/search?string=student&cursor=nope",
+            Some(&searcher.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "invalid_cursor");
+
+    let res = app.get("/profiles/search", Some(&searcher.access)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "malformed_query");
+}
+
+#[actix_web::test]
+async fn follow_and_unfollow() {
+    let app = common::spawn().await;
+    let alice = app.signup("alice").await;
+    let bobby = app.signup("bobby").await;
+
+    let res = app
+        .post(
+            &format!("/profiles/{}/follow", alice.id),
+            json!({}),
+            Some(&bobby.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["changed"], true);
+
+    let res = app
+        .post(
+            &format!("/profiles/{}/follow", alice.id),
+            json!({}),
+            Some(&bobby.access),
+        )
+        .await;
+    assert_eq!(res.body["changed"], false);
+    assert_eq!(follow_counts(&app.conn, &alice.id).await, (1, 0));
+    assert_eq!(follow_counts(&app.conn, &bobby.id).await, (0, 1));
+
+    let res = app
+        .get(
+            &format!("/profiles/{}/followers", alice.id),
+            Some(&alice.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["items"][0]["username"], "bobby");
+
+    let res = app
+        .post(
+            &format!("/profiles/{}/follow", bobby.id),
+            json!({}),
+            Some(&bobby.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.code(), "cannot_follow_self");
+
+    let res = app
+        .post(
+            "/profiles/no-such-user/follow",
+            json!({}),
+            Some(&bobby.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+    let res = app
+        .delete(
+            &format!("/profiles/{}/follow", alice.id),
+            Some(&bobby.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["changed"], true);
+
+    let res = app
+        .get(
+            &format!("/profiles/{}/followers", alice.id),
+            Some(&alice.access),
+        )
+        .await;
+    assert!(res.body["items"].as_array().unwrap().is_empty());
+
+    let res = app
+        .delete(
+            &format!("/profiles/{}/follow", alice.id),
+            Some(&bobby.access),
+        )
+        .await;
+    assert_eq!(res.body["changed"], false);
+    assert_eq!(follow_counts(&app.conn, &alice.id).await, (0, 0));
+    assert_eq!(follow_counts(&app.conn, &bobby.id).await, (0, 0));
+}
+
+#[actix_web::test]
+async fn deactivated_users_cannot_be_followed() {
+    let app = common::spawn().await;
+    let alice = app.signup("alice").await;
+    let bobby = app.signup("bobby").await;
+    app.conn
+        .execute(
+            "UPDATE users SET deactivated_at = CURRENT_TIMESTAMP WHERE id = ?1",
+            params![alice.id.clone()],
+        )
+        .await
+        .unwrap();
+
+    let res = app
+        .post(
+            &format!("/profiles/{}/follow", alice.id),
+            json!({}),
+            Some(&bobby.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert_eq!(follow_counts(&app.conn, &alice.id).await, (0, 0));
+}
+
+#[actix_web::test]
+async fn replacing_an_avatar_deletes_the_old_one() {
+    let app = common::spawn().await;
+    let user = app.signup("iris").await;
+
+    let mut png = Cursor::new(vec![]);
+    RgbImage::new(8, 8)
+        .write_to(&mut png, ImageFormat::Png)
+        .unwrap();
+    let png = png.into_inner();
+
+    let res = app.call(avatar(&png), Some(&user.access)).await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+    let old = media_path(&res.body["profile_url"]);
+    assert_eq!(app.get(&old, None).await.status, StatusCode::OK);
+
+    let res = app.call(avatar(&png), Some(&user.access)).await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+    let new = media_path(&res.body["profile_url"]);
+    assert_eq!(app.get(&old, None).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(app.get(&new, None).await.status, StatusCode::OK);
+
+    let res = app
+        .call(avatar(&vec![0; MAX_AVATAR_BYTES + 1]), Some(&user.access))
+        .await;
+    assert_eq!(res.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(res.code(), "payload_too_large");
+}
+
+/// A profile picture upload of `image` as a PNG.
+fn avatar(image: &[u8]) -> TestRequest {
+    let mut body = b"--boundary\r\n\
+        Content-Disposition: form-data; name=\"image\"; filename=\"me.png\"\r\n\
+        Content-Type: image/png\r\n\r\n"
+        .to_vec();
+    body.extend_from_slice(image);
+    body.extend_from_slice(b"\r\n--boundary--\r\n");
+
+    TestRequest::post()
+        .uri("/profiles/avatar")
+        .insert_header(("Content-Type", "multipart/form-data; boundary=boundary"))
+        .set_payload(body)
+}
+
+/// The path a local storage URL is served under.
+fn media_path(url: &serde_json::Value) -> String {
+    url.as_str()
+        .unwrap()
+        .strip_prefix("http://localhost")
+        .unwrap()
+        .to_string()
+}
+
+/// The `(followers, following)` counters of `user`.
+async fn follow_counts(conn: &libsql::Connection, user: &str) -> (i64, i64) {
+    let mut rows = conn
+        .query(
+            "SELECT followers, following FROM users WHERE id = ?1",
+            params![user],
+        )
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    (row.get(0).unwrap(), row.get(1).unwrap())
+}
+
+#[actix_web::test]
+async fn updating_some_fields_leaves_the_rest_alone() {
+    let app = common::spawn().await;
+    let user = app.signup("hazel").await;
+
+    let res = app
+        .call(
+            TestRequest::post()
+                .uri("/profiles/update")
+                .set_form([("last_name", "Grey")]),
+            Some(&user.access),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+
+    let mut rows = app
+        .conn
+        .query(
+            "SELECT first_name, last_name, bio FROM users WHERE id = ?1",
+            params![user.id.clone()],
+        )
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    assert_eq!(row.get::<String>(0).unwrap(), "Test");
+    assert_eq!(row.get::<String>(1).unwrap(), "Grey");
+    assert_eq!(row.get::<Option<String>>(2).unwrap(), None);
+}